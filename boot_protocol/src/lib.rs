@@ -0,0 +1,276 @@
+//! Types handed from the bootloader to the kernel.
+//!
+//! Everything in here crosses the boot boundary, so it is `#[repr(C)]`, holds raw pointers
+//! and lengths instead of references, and has its size pinned by a compile-time assertion.
+//! Any change to a layout must bump `LAYOUT_VERSION`.
+#![no_std]
+
+use core::mem::size_of;
+
+pub const BOOT_INFO_MAGIC: u64 = 0x534F_5249_5842_4F4F; // "SORIXBOO"
+pub const LAYOUT_VERSION: u32 = 11;
+
+pub const PAGE_SIZE: u64 = 4096;
+
+// UEFI memory types the kernel cares about
+pub const RESERVED: u32 = 0;
+pub const LOADER_CODE: u32 = 1;
+pub const LOADER_DATA: u32 = 2;
+pub const BOOT_SERVICES_CODE: u32 = 3;
+pub const BOOT_SERVICES_DATA: u32 = 4;
+pub const RUNTIME_SERVICES_CODE: u32 = 5;
+pub const RUNTIME_SERVICES_DATA: u32 = 6;
+pub const CONVENTIONAL: u32 = 7;
+pub const UNUSABLE: u32 = 8;
+pub const MMIO: u32 = 11;
+pub const MMIO_PORT_SPACE: u32 = 12;
+
+// Pixel formats, same values as the UEFI EFI_GRAPHICS_PIXEL_FORMAT
+pub const PIXEL_RGB: u32 = 0;
+pub const PIXEL_BGR: u32 = 1;
+pub const PIXEL_BITMASK: u32 = 2;
+
+pub const BOOT_PHASE_NAME_LEN: usize = 16;
+
+/// A linear framebuffer with 32-bit pixels. The channel masks are filled in for every
+/// pixel format, so drawing code only needs them to encode a color.
+#[repr(C)]
+pub struct FramebufferInfo {
+    pub base: *mut u32,
+    pub size: usize, // In bytes
+    pub width: usize,
+    pub height: usize,
+    pub pixels_per_scan_line: usize,
+    pub pixel_format: u32,
+    pub red_mask: u32,
+    pub green_mask: u32,
+    pub blue_mask: u32,
+    pub reserved_mask: u32,
+}
+
+/// Location of the final UEFI memory map, as returned by `exit_boot_services`.
+/// Entries are `descriptor_size` bytes apart, which is not necessarily the size of a MemoryDescriptor.
+#[repr(C)]
+pub struct MemoryMapInfo {
+    pub descriptors: *const u8,
+    pub descriptor_count: usize,
+    pub descriptor_size: usize,
+    pub descriptor_version: u32,
+}
+
+/// Mirror of the UEFI EFI_MEMORY_DESCRIPTOR
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct MemoryDescriptor {
+    pub ty: u32,
+    pub phys_start: u64,
+    pub virt_start: u64,
+    pub page_count: u64,
+    pub attribute: u64,
+}
+
+/// TSC reading taken when a boot phase finished. The phase ran since the previous timestamp.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct BootTimestamp {
+    pub name: [u8; BOOT_PHASE_NAME_LEN], // UTF-8, zero padded
+    pub tsc: u64,
+}
+
+#[repr(C)]
+pub struct BootInfo {
+    pub magic: u64,
+    pub version: u32,
+    pub size: u32, // size_of::<BootInfo>() as seen by the bootloader
+    pub framebuffer: *mut FramebufferInfo,
+    pub memory_map: MemoryMapInfo,
+    // The kernel image is one physically contiguous block mapped at kernel_virtual_base
+    pub kernel_physical_base: u64,
+    pub kernel_virtual_base: u64,
+    pub kernel_size: u64,
+    // UTF-8 kernel command line from the bootloader config, in LOADER_DATA memory. Null when empty.
+    pub cmdline: *const u8,
+    pub cmdline_len: usize,
+    // Initial ramdisk (a ustar archive) in LOADER_DATA pages. Zero when the boot entry has none.
+    pub initrd_base: u64,
+    pub initrd_size: u64,
+    // Firmware tables, physical addresses or 0 if the firmware has none.
+    // Runtime services were switched to virtual mode with an identity map, see MemoryDescriptor::virt_start.
+    pub acpi_rsdp: u64, // ACPI 2.0+ RSDP, or the ACPI 1.0 one on old firmware
+    pub smbios_entry: u64, // SMBIOS 3 entry point, or the 32-bit one on old firmware
+    pub runtime_services: u64, // EFI_RUNTIME_SERVICES table
+    // Copies of the kernel's .symtab and .strtab in LOADER_DATA pages. Zero if the kernel is stripped.
+    // Symbol values are link-time addresses, before the KASLR slide.
+    pub symtab_base: u64,
+    pub symtab_size: u64,
+    pub strtab_base: u64,
+    pub strtab_size: u64,
+    // The stack _start runs on, in LOADER_DATA pages mapped writable at stack_virtual_base.
+    // The page below stack_virtual_base is left unmapped as a guard; the initial RSP is the top of the stack.
+    pub stack_virtual_base: u64,
+    pub stack_physical_base: u64,
+    pub stack_size: u64,
+    // Bootloader phase timestamps in LOADER_DATA memory, oldest first. The frequency is 0 if calibration failed.
+    pub tsc_frequency: u64, // Hz
+    pub timestamps: *const BootTimestamp,
+    pub timestamp_count: usize,
+}
+
+const _: () = assert!(size_of::<FramebufferInfo>() == 64);
+const _: () = assert!(size_of::<MemoryMapInfo>() == 32);
+const _: () = assert!(size_of::<MemoryDescriptor>() == 40);
+const _: () = assert!(size_of::<BootTimestamp>() == 24);
+const _: () = assert!(size_of::<BootInfo>() == 216);
+
+impl MemoryDescriptor {
+    pub fn phys_end(&self) -> u64 {
+        self.phys_start + self.page_count * PAGE_SIZE
+    }
+
+    pub fn is_usable(&self) -> bool {
+        self.ty == CONVENTIONAL
+    }
+
+    /// Usable memory plus the boot services regions the firmware gave up at exit_boot_services.
+    pub fn is_reclaimable(&self) -> bool {
+        matches!(self.ty, CONVENTIONAL | BOOT_SERVICES_CODE | BOOT_SERVICES_DATA)
+    }
+
+    pub fn contains(&self, address: u64) -> bool {
+        address >= self.phys_start && address < self.phys_end()
+    }
+}
+
+impl BootTimestamp {
+    /// Names longer than BOOT_PHASE_NAME_LEN bytes are cut short
+    pub fn new(name: &str, tsc: u64) -> Self {
+        let mut bytes = [0u8; BOOT_PHASE_NAME_LEN];
+        let len = name.len().min(BOOT_PHASE_NAME_LEN);
+        bytes[..len].copy_from_slice(&name.as_bytes()[..len]);
+        Self { name: bytes, tsc }
+    }
+
+    pub fn name(&self) -> &str {
+        let len = self.name.iter().position(|b| *b == 0).unwrap_or(BOOT_PHASE_NAME_LEN);
+        match core::str::from_utf8(&self.name[..len]) {
+            Ok(name) => name,
+            // A multi-byte character was cut in half by new
+            Err(e) => core::str::from_utf8(&self.name[..e.valid_up_to()]).unwrap_or(""),
+        }
+    }
+}
+
+impl BootInfo {
+    pub fn new(framebuffer: *mut FramebufferInfo) -> Self {
+        Self {
+            magic: BOOT_INFO_MAGIC,
+            version: LAYOUT_VERSION,
+            size: size_of::<BootInfo>() as u32,
+            framebuffer,
+            memory_map: MemoryMapInfo {
+                descriptors: core::ptr::null(),
+                descriptor_count: 0,
+                descriptor_size: 0,
+                descriptor_version: 0,
+            },
+            kernel_physical_base: 0,
+            kernel_virtual_base: 0,
+            kernel_size: 0,
+            cmdline: core::ptr::null(),
+            cmdline_len: 0,
+            initrd_base: 0,
+            initrd_size: 0,
+            acpi_rsdp: 0,
+            smbios_entry: 0,
+            runtime_services: 0,
+            symtab_base: 0,
+            symtab_size: 0,
+            strtab_base: 0,
+            strtab_size: 0,
+            stack_virtual_base: 0,
+            stack_physical_base: 0,
+            stack_size: 0,
+            tsc_frequency: 0,
+            timestamps: core::ptr::null(),
+            timestamp_count: 0,
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.magic == BOOT_INFO_MAGIC
+            && self.version == LAYOUT_VERSION
+            && self.size as usize == size_of::<BootInfo>()
+    }
+
+    /// # Safety
+    /// The pointer and length fields must describe memory that stays mapped, readable and unchanged for as long
+    /// as `self` is borrowed, as they do in the BootInfo the bootloader hands over. The same goes for every
+    /// accessor below that reads through one of them.
+    pub unsafe fn memory_descriptors(&self) -> impl Iterator<Item = MemoryDescriptor> + '_ {
+        let map = &self.memory_map;
+        (0..map.descriptor_count).map(move |i| unsafe {
+            core::ptr::read_unaligned(map.descriptors.add(i * map.descriptor_size) as *const MemoryDescriptor)
+        })
+    }
+
+    /// The kernel command line, or an empty string if there is none or it is not valid UTF-8.
+    ///
+    /// # Safety
+    /// `cmdline` and `cmdline_len` must be valid, see `memory_descriptors`.
+    pub unsafe fn cmdline(&self) -> &str {
+        if self.cmdline.is_null() {
+            return "";
+        }
+
+        let bytes = unsafe { core::slice::from_raw_parts(self.cmdline, self.cmdline_len) };
+        core::str::from_utf8(bytes).unwrap_or("")
+    }
+
+    /// # Safety
+    /// `initrd_base` and `initrd_size` must be valid, see `memory_descriptors`.
+    pub unsafe fn initrd(&self) -> Option<&[u8]> {
+        if self.initrd_base == 0 {
+            return None;
+        }
+
+        Some(unsafe { core::slice::from_raw_parts(self.initrd_base as *const u8, self.initrd_size as usize) })
+    }
+
+    /// The kernel's symbol table and its string table, if the bootloader passed them
+    ///
+    /// # Safety
+    /// The symtab and strtab fields must be valid, see `memory_descriptors`.
+    pub unsafe fn symbols(&self) -> Option<(&[u8], &[u8])> {
+        if self.symtab_base == 0 || self.strtab_base == 0 {
+            return None;
+        }
+
+        unsafe {
+            Some((
+                core::slice::from_raw_parts(self.symtab_base as *const u8, self.symtab_size as usize),
+                core::slice::from_raw_parts(self.strtab_base as *const u8, self.strtab_size as usize),
+            ))
+        }
+    }
+
+    /// # Safety
+    /// `timestamps` and `timestamp_count` must be valid, see `memory_descriptors`.
+    pub unsafe fn timestamps(&self) -> &[BootTimestamp] {
+        if self.timestamps.is_null() {
+            return &[];
+        }
+
+        unsafe { core::slice::from_raw_parts(self.timestamps, self.timestamp_count) }
+    }
+
+    /// Finds the largest usable region of at least `min_size` bytes that starts at or above `min_addr`.
+    ///
+    /// # Safety
+    /// The memory map must be valid, see `memory_descriptors`.
+    pub unsafe fn largest_usable_region(&self, min_size: u64, min_addr: u64) -> Option<MemoryDescriptor> {
+        unsafe { self.memory_descriptors() }
+            .filter(|desc| desc.is_usable() && desc.phys_start >= min_addr)
+            .filter(|desc| desc.page_count * PAGE_SIZE >= min_size)
+            .max_by_key(|desc| desc.page_count)
+    }
+}
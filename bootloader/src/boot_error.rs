@@ -0,0 +1,73 @@
+use alloc::string::String;
+use core::fmt::Write;
+
+use formats::config::BootConfig;
+use log::*;
+use uefi::proto::console::text::Color;
+use uefi::{system, Status};
+
+use crate::{boot_log, menu};
+
+/// How long the error stays on screen before the bootloader returns to the firmware anyway
+const PAUSE_SECONDS: u32 = 30;
+
+/// Everything that stops the bootloader before it reaches the kernel
+#[derive(Debug)]
+pub enum BootError {
+    Heap(Status),
+    NoKernelVolume(String),
+    NoBootableEntry,
+    Graphics(Status),
+    NoFramebuffer,
+    KernelStack(Status),
+    MemoryMap(Status),
+    PageTables,
+}
+
+impl core::fmt::Display for BootError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            BootError::Heap(status) => write!(f, "could not allocate the bootloader heap: {}", status),
+            BootError::NoKernelVolume(wanted) => write!(f, "no readable volume {}", wanted),
+            BootError::NoBootableEntry => write!(f, "none of the boot entries could be loaded"),
+            BootError::Graphics(status) => write!(f, "could not open the graphics output: {}", status),
+            BootError::NoFramebuffer => write!(f, "the graphics mode has no linear framebuffer"),
+            BootError::KernelStack(status) => write!(f, "could not allocate the kernel stack: {}", status),
+            BootError::MemoryMap(status) => write!(f, "could not read the memory map: {}", status),
+            BootError::PageTables => write!(f, "could not build the kernel page tables"),
+        }
+    }
+}
+
+impl BootError {
+    pub fn status(&self) -> Status {
+        match self {
+            BootError::Heap(status) | BootError::Graphics(status) | BootError::MemoryMap(status) => *status,
+            BootError::KernelStack(status) => *status,
+            BootError::NoKernelVolume(_) => Status::NOT_FOUND,
+            BootError::NoBootableEntry => Status::LOAD_ERROR,
+            BootError::NoFramebuffer => Status::UNSUPPORTED,
+            BootError::PageTables => Status::OUT_OF_RESOURCES,
+        }
+    }
+}
+
+/// Saves the boot log to the kernel volume, if the config is known, shows the error on screen,
+/// even with logging turned off, and waits for a key (or PAUSE_SECONDS) so it can be read
+/// before the firmware takes over again.
+pub fn report(error: &BootError, config: Option<&BootConfig>) -> Status {
+    error!("FATAL: {}", error);
+    if let Some(config) = config {
+        boot_log::save_to_volume(config);
+    }
+
+    system::with_stdout(|out| {
+        let _ = out.set_color(Color::White, Color::Red);
+        let _ = write!(out, "\nSorix could not boot: {}", error);
+        let _ = out.set_color(Color::LightGray, Color::Black);
+        let _ = writeln!(out, "\nPress any key to return to the firmware");
+    });
+    let _ = menu::wait_for_key_timeout(PAUSE_SECONDS);
+
+    error.status()
+}
\ No newline at end of file
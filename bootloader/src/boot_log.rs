@@ -0,0 +1,150 @@
+use alloc::format;
+use alloc::string::String;
+use core::fmt::Write;
+
+use formats::config::BootConfig;
+use log::*;
+use spin::mutex::Mutex;
+use uefi::boot::{self, OpenProtocolAttributes, OpenProtocolParams};
+use uefi::proto::console::serial::Serial;
+use uefi::proto::media::file::{Directory, File, FileAttribute, FileInfo, FileMode, RegularFile};
+use uefi::{cstr16, runtime, system, CStr16, Handle};
+
+use crate::dir_management::find_kernel_volume;
+
+const LOG_DIR: &CStr16 = cstr16!("\\boot");
+pub const LOG_PATH: &CStr16 = cstr16!("\\boot\\bootlog.txt");
+/// Past this size the log file starts over instead of growing without bound
+const MAX_LOG_FILE_SIZE: u64 = 1024 * 1024;
+
+struct SerialHandle(Handle);
+
+// Boot services are single threaded, so the handle is only ever used from one thread
+unsafe impl Send for SerialHandle {}
+
+struct LogState {
+    pending: String, // Not yet written to the log file
+    serial: Option<SerialHandle>,
+}
+
+static STATE: Mutex<LogState> = Mutex::new(LogState { pending: String::new(), serial: None });
+
+/// Sends every record to the console, the first serial port and a buffer for LOG_PATH
+struct BootLogger;
+
+static LOGGER: BootLogger = BootLogger;
+
+impl Log for BootLogger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        let file = record.file().unwrap_or("");
+        let line = format!("[{:>5}]: {:>12}@{:03}: {}\n", record.level(), file, record.line().unwrap_or(0), record.args());
+
+        system::with_stdout(|out| {
+            let _ = out.write_str(&line);
+        });
+
+        let mut state = STATE.lock();
+        if let Some(serial) = &state.serial {
+            write_serial(serial.0, &line);
+        }
+        state.pending.push_str(&line);
+    }
+
+    fn flush(&self) {}
+}
+
+fn write_serial(handle: Handle, text: &str) {
+    let params = OpenProtocolParams {
+        handle,
+        agent: boot::image_handle(),
+        controller: None,
+    };
+    // GetProtocol leaves the port to the firmware console, which may be mirrored on it too
+    if let Ok(mut serial) = unsafe { boot::open_protocol::<Serial>(params, OpenProtocolAttributes::GetProtocol) } {
+        for line in text.split_inclusive('\n') {
+            let _ = serial.write(line.trim_end_matches('\n').as_bytes());
+            if line.ends_with('\n') {
+                let _ = serial.write(b"\r\n");
+            }
+        }
+    }
+}
+
+/// Installs the logger. Needs the heap, since records are kept until `save` writes them out.
+pub fn init() {
+    let serial = boot::get_handle_for_protocol::<Serial>().ok().map(SerialHandle);
+    let has_serial = serial.is_some();
+    STATE.lock().serial = serial;
+
+    if log::set_logger(&LOGGER).is_err() {
+        return;
+    }
+    log::set_max_level(LevelFilter::Info);
+
+    let mut header = String::from("\n--- Sorix bootloader");
+    if let Ok(time) = runtime::get_time() {
+        let _ = write!(header, " started {}", time);
+    }
+    let _ = writeln!(header, " on {} {:#x}, UEFI {} ---",
+        system::firmware_vendor(), system::firmware_revision(), system::uefi_revision());
+    STATE.lock().pending.push_str(&header);
+
+    if !has_serial {
+        info!("No serial port, logging to the console and {} only", LOG_PATH);
+    }
+}
+
+/// Appends everything logged since the last save to LOG_PATH on `root`.
+/// Must run before exit_boot_services; anything logged after the last save is lost.
+pub fn save(root: &mut Directory) {
+    let text = core::mem::take(&mut STATE.lock().pending);
+    if text.is_empty() {
+        return;
+    }
+
+    // Opening a directory with Create makes it if it is missing
+    if let Ok(dir) = root.open(LOG_DIR, FileMode::CreateReadWrite, FileAttribute::DIRECTORY) {
+        dir.close();
+    }
+
+    let mut file = match open_log_file(root) {
+        Some(file) => file,
+        None => {
+            warn!("Could not open {}, the boot log is not saved", LOG_PATH);
+            return;
+        }
+    };
+
+    match file.write(text.as_bytes()) {
+        Ok(()) => {
+            let _ = file.flush();
+        },
+        Err(e) => warn!("Failed to write {}! Error: {}", LOG_PATH, e.status()),
+    }
+}
+
+/// Opens the log file positioned at its end, starting a fresh file if the old one got too big
+fn open_log_file(root: &mut Directory) -> Option<RegularFile> {
+    let mut file = root.open(LOG_PATH, FileMode::CreateReadWrite, FileAttribute::empty()).ok()?.into_regular_file()?;
+
+    let mut info_buffer = [0u8; 512];
+    let size = file.get_info::<FileInfo>(&mut info_buffer).map(|info| info.file_size()).unwrap_or(0);
+    if size > MAX_LOG_FILE_SIZE {
+        let _ = file.delete();
+        return root.open(LOG_PATH, FileMode::CreateReadWrite, FileAttribute::empty()).ok()?.into_regular_file();
+    }
+
+    file.set_position(RegularFile::END_OF_FILE).ok()?;
+    Some(file)
+}
+
+/// Saves the log on a failed boot, when the kernel volume may not have been opened yet
+pub fn save_to_volume(config: &BootConfig) {
+    if let Some(mut root) = find_kernel_volume(config) {
+        save(&mut root);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,66 @@
+use alloc::vec::Vec;
+use formats::config::{BootConfig, LogLevel};
+use log::*;
+use uefi::boot;
+
+use crate::dir_management::read_file;
+
+pub const CONFIG_PATH: &str = "\\boot\\sorix.cfg";
+
+fn read_config_file() -> Option<Vec<u8>> {
+    // The config sits next to the bootloader, on the volume the firmware loaded us from
+    let mut sfs = match boot::get_image_file_system(boot::image_handle()) {
+        Ok(sfs) => sfs,
+        Err(e) => {
+            warn!("Could not open the boot volume to read the config! Error: {}", e);
+            return None;
+        }
+    };
+    let mut root = match sfs.open_volume() {
+        Ok(root) => root,
+        Err(e) => {
+            warn!("Could not open the boot volume root directory! Error: {}", e);
+            return None;
+        }
+    };
+
+    read_file(&mut root, CONFIG_PATH)
+}
+
+/// Reads the boot config. A missing or broken file is not fatal: the defaults boot `kernel` from the `OS` volume.
+pub fn load_config() -> BootConfig {
+    let data = match read_config_file() {
+        Some(data) => data,
+        None => {
+            info!("No config at {}, using defaults", CONFIG_PATH);
+            return BootConfig::default();
+        }
+    };
+
+    let text = match core::str::from_utf8(&data) {
+        Ok(text) => text,
+        Err(e) => {
+            error!("{} is not valid UTF-8 ({}), using defaults", CONFIG_PATH, e);
+            return BootConfig::default();
+        }
+    };
+
+    match BootConfig::parse(text) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("{} line {}: {:?}, using defaults", CONFIG_PATH, e.line, e.kind);
+            BootConfig::default()
+        }
+    }
+}
+
+pub fn level_filter(level: LogLevel) -> LevelFilter {
+    match level {
+        LogLevel::Off => LevelFilter::Off,
+        LogLevel::Error => LevelFilter::Error,
+        LogLevel::Warn => LevelFilter::Warn,
+        LogLevel::Info => LevelFilter::Info,
+        LogLevel::Debug => LevelFilter::Debug,
+        LogLevel::Trace => LevelFilter::Trace,
+    }
+}
\ No newline at end of file
@@ -1,7 +1,13 @@
 use core::ptr::NonNull;
 
+use alloc::vec;
+use alloc::vec::Vec;
 use log::*;
-use uefi::{boot::{self, MemoryType}, proto::media::{file::{Directory, File, FileAttribute, FileInfo, FileMode, FileSystemVolumeLabel}, fs::SimpleFileSystem}, CStr16, Identify, Status};
+use formats::config::BootConfig;
+use uefi::boot::{OpenProtocolAttributes, OpenProtocolParams};
+use uefi::proto::device_path::{media::PartitionSignature, DevicePath, DevicePathNodeEnum};
+use uefi::proto::loaded_image::LoadedImage;
+use uefi::{boot::{self, AllocateType, MemoryType, PAGE_SIZE}, proto::media::{file::{Directory, File, FileAttribute, FileInfo, FileMode, FileSystemVolumeLabel, RegularFile}, fs::SimpleFileSystem}, CString16, Guid, Handle, Identify, Status};
 
 pub struct KernelElfData {
     pub _len: usize,
@@ -17,75 +23,133 @@ impl KernelElfData {
     }
 }
 
-pub fn find_kernel_volume() -> Option<Directory> {
-    let handle_buffer = match boot::locate_handle_buffer(boot::SearchType::ByProtocol(&SimpleFileSystem::GUID)) {
-        Ok(buffer) => buffer,
-        Err(err) => {
-            error!("Could not locate an SFS handle! Error: {}", err);
-            return None;
+/// Volumes to search for the kernel: the device the bootloader was loaded from first, then every other file system
+fn candidate_volumes() -> Vec<Handle> {
+    let mut handles = Vec::new();
+    match boot::open_protocol_exclusive::<LoadedImage>(boot::image_handle()) {
+        Ok(image) => handles.extend(image.device()),
+        Err(e) => warn!("Could not find the device the bootloader was loaded from! Error: {}", e),
+    }
+
+    match boot::locate_handle_buffer(boot::SearchType::ByProtocol(&SimpleFileSystem::GUID)) {
+        Ok(buffer) => {
+            for handle in buffer.iter() {
+                if !handles.contains(handle) {
+                    handles.push(*handle);
+                }
+            }
         },
+        Err(err) => error!("Could not locate an SFS handle! Error: {}", err),
+    }
+
+    handles
+}
+
+/// The GPT partition GUID from the handle's device path, if it is a GPT partition
+fn partition_guid(handle: Handle) -> Option<Guid> {
+    let params = OpenProtocolParams {
+        handle,
+        agent: boot::image_handle(),
+        controller: None,
     };
-    let handle_iter = handle_buffer.iter();
-    let os_label = widestring::u16cstr!("OS").as_slice_with_nul();
-    for handle in handle_iter {
-        let mut sfs = match boot::open_protocol_exclusive::<SimpleFileSystem>(*handle) {
-            Ok(sfs) => {
-                info!("Successfully opened an SFS");
-                sfs
-            },
-            Err(e) => {
-                error!("Failed to open SFS protocol! Error: {}", e);
-                return None;
-            }
-        };
-        let mut sfs_volume = match sfs.open_volume() {
-            Ok(v) => {
-                info!("Successfully opened the SFS volume");
-                v
-            },
-            Err(e) => {
-                error!("Failed to open SFS volume! Error: {}", e);
-                return None;
-            }
-        };
+    let device_path = unsafe { boot::open_protocol::<DevicePath>(params, OpenProtocolAttributes::GetProtocol) }.ok()?;
 
-        let mut volume_buffer = [0u8; 512];
-        let volume_info = match sfs_volume.get_info::<FileSystemVolumeLabel>(&mut volume_buffer) {
-            Ok(vi) => {
-                info!("Successfully got volume info");
-                vi
-            },
-            Err(e) => {
-                error!("Failed to get SFS Volume Info! Error: {}", e);
-                return None;
-            }
-        };
+    device_path.node_iter().find_map(|node| match node.as_enum() {
+        Ok(DevicePathNodeEnum::MediaHardDrive(drive)) => match drive.partition_signature() {
+            PartitionSignature::Guid(guid) => Some(guid),
+            _ => None,
+        },
+        _ => None,
+    })
+}
 
-        let volume_label = volume_info.volume_label();
-        let os_label = match CStr16::from_u16_with_nul(&os_label) {
-            Ok(label) => label,
-            Err(e) => {
-                error!("Failed to convert OS volume label to CStr16. Error: {}", e);
-                return None;
-            }
+fn open_volume(handle: Handle) -> Option<Directory> {
+    let mut sfs = match boot::open_protocol_exclusive::<SimpleFileSystem>(handle) {
+        Ok(sfs) => sfs,
+        Err(e) => {
+            warn!("Skipping a volume whose SFS protocol could not be opened! Error: {}", e);
+            return None;
+        }
+    };
+
+    match sfs.open_volume() {
+        Ok(v) => Some(v),
+        Err(e) => {
+            warn!("Skipping a volume that could not be opened! Error: {}", e);
+            None
+        }
+    }
+}
+
+fn has_label(root: &mut Directory, label: &CString16) -> bool {
+    let mut volume_buffer = [0u8; 512];
+    match root.get_info::<FileSystemVolumeLabel>(&mut volume_buffer) {
+        Ok(info) => {
+            info!("VOLUME LABEL: {}", info.volume_label());
+            info.volume_label() == label.as_ref()
+        },
+        Err(e) => {
+            warn!("Failed to get SFS Volume Info! Error: {}", e);
+            false
+        }
+    }
+}
+
+fn holds_kernel(root: &mut Directory, config: &BootConfig) -> bool {
+    config.entries.iter().any(|entry| {
+        let path = match CString16::try_from(entry.kernel_path.as_str()) {
+            Ok(path) => path,
+            Err(_) => return false,
         };
-        info!("VOLUME LABEL: {}", volume_label);
-        if volume_label.eq(os_label) {
-            return Some(sfs_volume);
-        } else {
-            warn!("Volume is not an OS volume or does not contain a kernel binary. Trying again.");
+        match root.open(&path, FileMode::Read, FileAttribute::empty()) {
+            Ok(file) => file.into_regular_file().is_some(),
+            Err(_) => false,
+        }
+    })
+}
+
+/// Finds the volume the kernel is on, as described in `formats::config`. Volumes that cannot be opened or read are skipped.
+pub fn find_kernel_volume(config: &BootConfig) -> Option<Directory> {
+    let wanted_guid = config.volume_guid.map(Guid::from_bytes);
+    let label = match CString16::try_from(config.volume_label.as_str()) {
+        Ok(l) => Some(l),
+        Err(e) => {
+            warn!("Volume label {} is not valid UCS-2, only looking for kernel paths. Error: {:?}", config.volume_label, e);
+            None
+        }
+    };
+
+    for handle in candidate_volumes() {
+        if let Some(guid) = wanted_guid && partition_guid(handle) != Some(guid) {
             continue;
         }
+
+        let mut root = match open_volume(handle) {
+            Some(root) => root,
+            None => continue,
+        };
+
+        if let Some(guid) = wanted_guid {
+            info!("Found kernel volume by partition GUID {}", guid);
+            return Some(root);
+        }
+        if label.as_ref().is_some_and(|label| has_label(&mut root, label)) {
+            info!("Found kernel volume by label {}", config.volume_label);
+            return Some(root);
+        }
+        if holds_kernel(&mut root, config) {
+            info!("Found kernel volume by kernel path");
+            return Some(root);
+        }
+        warn!("Volume is not an OS volume or does not contain a kernel binary. Trying again.");
     }
 
     None
 }
 
-pub fn open_kernel_elf(dir: &mut Directory) -> (Option<KernelElfData>, Status) {
+pub fn open_kernel_elf(dir: &mut Directory, path: &str) -> (Option<KernelElfData>, Status) {
     info!("Retrieving kernel binary filename");
-    let mut filename_buf = [0u16; 7];
-
-    let filename = match CStr16::from_str_with_buf("kernel", &mut filename_buf) {
+    let filename = match CString16::try_from(path) {
         Ok(filename) => {
             info!("Got kernel filename");
             filename
@@ -95,7 +159,7 @@ pub fn open_kernel_elf(dir: &mut Directory) -> (Option<KernelElfData>, Status) {
             return (None, Status::ABORTED);
         },
     };
-    let mut kernel_file = match dir.open(filename, FileMode::Read, FileAttribute::empty()) {
+    let mut kernel_file = match dir.open(&filename, FileMode::Read, FileAttribute::empty()) {
         Ok(kf) => match kf.into_regular_file() {
             Some(rf) => {
                 info!("Opened kernel binary for proper reading");
@@ -156,4 +220,84 @@ pub fn open_kernel_elf(dir: &mut Directory) -> (Option<KernelElfData>, Status) {
 
     info!("Continuing boot process");
     (Some(kernel_data), Status::SUCCESS)
+}
+
+/// Opens `path` for reading and returns it with its size in bytes.
+fn open_regular_file(dir: &mut Directory, path: &str) -> Option<(RegularFile, usize)> {
+    let filename = match CString16::try_from(path) {
+        Ok(filename) => filename,
+        Err(e) => {
+            error!("Path {} is not valid UCS-2. Error: {:?}", path, e);
+            return None;
+        }
+    };
+
+    let mut file = match dir.open(&filename, FileMode::Read, FileAttribute::empty()) {
+        Ok(f) => f.into_regular_file()?,
+        Err(e) => {
+            info!("Could not open {}: {}", path, e);
+            return None;
+        }
+    };
+
+    let mut info_buffer = [0u8; 512];
+    match file.get_info::<FileInfo>(&mut info_buffer) {
+        Ok(info) => {
+            let size = info.file_size() as usize;
+            Some((file, size))
+        },
+        Err(e) => {
+            error!("Failed to get info for {}! Error: {}", path, e);
+            None
+        }
+    }
+}
+
+/// Reads a whole file into memory. Meant for small files such as the boot config.
+pub fn read_file(dir: &mut Directory, path: &str) -> Option<Vec<u8>> {
+    let (mut file, size) = open_regular_file(dir, path)?;
+
+    let mut data = vec![0u8; size];
+    match file.read(&mut data) {
+        Ok(len) => {
+            data.truncate(len);
+            Some(data)
+        },
+        Err(e) => {
+            error!("Failed to read {}! Error: {}", path, e);
+            None
+        }
+    }
+}
+
+/// Reads the initial ramdisk into LOADER_DATA pages, which the kernel keeps after boot.
+pub fn load_initrd(dir: &mut Directory, path: &str) -> Option<&'static [u8]> {
+    let (mut file, size) = open_regular_file(dir, path)?;
+
+    let page_count = size.div_ceil(PAGE_SIZE).max(1);
+    let pages = match boot::allocate_pages(AllocateType::AnyPages, MemoryType::LOADER_DATA, page_count) {
+        Ok(pages) => pages,
+        Err(e) => {
+            error!("Failed to allocate {} pages for the initrd! Error: {}", page_count, e);
+            return None;
+        }
+    };
+    let buffer = unsafe { core::slice::from_raw_parts_mut(pages.as_ptr(), size) };
+
+    match file.read(buffer) {
+        Ok(len) if len == size => {
+            info!("Loaded initrd {} ({} bytes) at {:#x}", path, size, pages.as_ptr() as usize);
+            Some(buffer)
+        },
+        result => {
+            match result {
+                Ok(len) => error!("Short read of initrd {}: {} of {} bytes", path, len, size),
+                Err(e) => error!("Failed to read initrd {}! Error: {}", path, e),
+            }
+            unsafe {
+                let _ = boot::free_pages(pages, page_count);
+            }
+            None
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,46 @@
+use core::arch::asm;
+use core::arch::x86_64::{__cpuid, _rdtsc};
+
+use log::*;
+
+const SLIDE_ALIGN: usize = 0x200000; // 2 MiB
+const SLIDE_WINDOW: usize = 0x40000000; // 1 GiB above the link address, staying inside the top 2 GiB
+const RDRAND_RETRIES: usize = 10;
+
+fn rdrand() -> Option<u64> {
+    // CPUID.01H:ECX bit 30
+    if __cpuid(1).ecx & (1 << 30) == 0 {
+        return None;
+    }
+
+    for _ in 0..RDRAND_RETRIES {
+        let value: u64;
+        let ok: u8;
+        unsafe {
+            asm!("rdrand {}", "setc {}", out(reg) value, out(reg_byte) ok, options(nomem, nostack));
+        }
+        if ok != 0 {
+            return Some(value);
+        }
+    }
+
+    None
+}
+
+fn random_u64() -> u64 {
+    match rdrand() {
+        Some(value) => value,
+        None => {
+            warn!("RDRAND unavailable, falling back to the TSC for the kernel base");
+            unsafe { _rdtsc() }
+        }
+    }
+}
+
+/// Picks a 2 MiB aligned base for a position-independent kernel of `image_size` bytes linked at `linked_base`.
+pub fn randomize_base(linked_base: usize, image_size: usize) -> usize {
+    let slots = SLIDE_WINDOW.saturating_sub(image_size.next_multiple_of(SLIDE_ALIGN)) / SLIDE_ALIGN + 1;
+    let slot = (random_u64() % slots as u64) as usize;
+
+    linked_base + slot * SLIDE_ALIGN
+}
\ No newline at end of file
@@ -1,269 +1,243 @@
-use core::borrow::Borrow;
+mod kaslr;
 
 use alloc::vec::Vec;
+use core::ptr::NonNull;
 use log::*;
-use num_enum::{IntoPrimitive, TryFromPrimitive};
-use uefi::boot::PAGE_SIZE;
+use formats::elf::{ELFHeader, ElfError, ProgramHeaderType, Relocation, PF_W, R_X86_64_NONE, R_X86_64_RELATIVE};
+use uefi::boot::{self, AllocateType, MemoryType, PAGE_SIZE};
+use uefi::Status;
+
+#[derive(Debug)]
+pub enum LoadError {
+    Elf(ElfError),
+    AllocationFailed(Status),
+    UnsupportedRelocation { r_type: u32, offset: usize },
+    IntegrityCheckFailed,
+}
 
-#[derive(Debug, TryFromPrimitive, IntoPrimitive)]
-#[repr(u8)]
-pub enum OSABI {
-    SystemV = 0x0,
-    HPUX = 0x01,
-    NetBSD = 0x02,
-    Linux = 0x03,
-    GNUHurd = 0x04,
-    Solaris = 0x06,
-    AIX = 0x07,
-    IRIX = 0x08,
-    FreeBSD = 0x09,
-    Tru64 = 0x0A,
-    NovellModesto = 0x0B,
-    OpenBSD = 0x0C,
-    OpenVMS = 0x0D,
-    NonStopKernel = 0x0E,
-    AROS = 0x0F,
-    FenixOS = 0x10,
-    NuxiCloudABI = 0x11,
-    OpenVOS = 0x12,
+impl core::fmt::Display for LoadError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            LoadError::Elf(e) => write!(f, "invalid kernel ELF: {:?}", e),
+            LoadError::AllocationFailed(status) => write!(f, "could not allocate the kernel image: {}", status),
+            LoadError::UnsupportedRelocation { r_type, offset } => {
+                write!(f, "unsupported relocation type {} at {:#x}", r_type, offset)
+            },
+            LoadError::IntegrityCheckFailed => write!(f, "the kernel failed its integrity check"),
+        }
+    }
 }
 
-#[derive(Debug, TryFromPrimitive, IntoPrimitive)]
-#[repr(u8)]
-pub enum Endianness {
-    Little = 0x1,
-    Big = 0x2,
+impl From<ElfError> for LoadError {
+    fn from(e: ElfError) -> Self {
+        LoadError::Elf(e)
+    }
 }
 
-#[derive(Debug, TryFromPrimitive, IntoPrimitive)]
-#[repr(u8)]
-pub enum ELFClass {
-    X32 = 0x1,
-    X64 = 0x2,
+pub struct KernelSegment {
+    pub virt_start: usize, // Page aligned
+    pub page_count: usize,
+    pub writable: bool,
 }
 
-#[derive(Debug, TryFromPrimitive, IntoPrimitive)]
-#[repr(u16)]
-pub enum ObjectFileType {
-    Unknown = 0x00,
-    Relocatable = 0x01,
-    Executable = 0x02,
-    SharedObject = 0x03,
-    CoreFile = 0x04,
+/// Where the kernel image ended up. The image is one physically contiguous block,
+/// so `physical_base + (vaddr - virtual_base)` locates any kernel virtual address.
+pub struct LoadedKernel {
+    pub entry: usize,
+    pub physical_base: usize,
+    pub virtual_base: usize,
+    pub page_count: usize,
+    pub segments: Vec<KernelSegment>,
+    pub symbols: Option<KernelSymbols>,
 }
 
-#[derive(Debug, TryFromPrimitive, IntoPrimitive)]
-#[repr(u16)]
-pub enum ISA {
-    Unknown = 0x00,
-    X86 = 0x03,
-    ARM = 0x28,
-    AMD64 = 0x3E,
+/// Copies of the kernel's .symtab and .strtab, back to back in their own LOADER_DATA pages
+pub struct KernelSymbols {
+    pub symtab: &'static [u8],
+    pub strtab: &'static [u8],
+    page_count: usize,
 }
 
-#[derive(Debug, TryFromPrimitive, IntoPrimitive)]
-#[repr(u32)]
-pub enum ProgramHeaderType {
-    Null = 0x00000000,
-    Load = 0x00000001,
-    Dynamic = 0x00000002,
-    Interp = 0x00000003,
-    Note = 0x00000004,
-    Reserved = 0x00000005,
-    Header = 0x00000006,
-    TLS = 0x00000007,
+/// The stack the kernel starts on. It sits in the higher half just above the image,
+/// with one unmapped guard page in between so an overflow faults instead of corrupting the kernel.
+pub struct KernelStack {
+    pub virtual_base: usize,
+    pub physical_base: usize,
+    pub page_count: usize,
 }
 
-pub struct ELFHeader {
-    pub elf_identity: ELFIdentity,
-    pub program_headers: Vec<ProgramHeader>,
-    pub e_type: ObjectFileType,
-    pub isa: ISA,
-    pub version: u32,
-    pub entry_function: usize,
-    pub program_header_offset: usize,
-    pub flags: u32,
-    pub header_size: u16, // Contains the size in bytes of the ELF header (64 bytes for 64-bit and 52 for 32-bit)
-    pub phentry_size: u16,
-    pub phentry_amount: u16,
+impl KernelStack {
+    /// The initial RSP. Stacks grow down, so this is one past the last byte.
+    pub fn top(&self) -> usize {
+        self.virtual_base + self.page_count * PAGE_SIZE
+    }
 }
 
-impl ELFHeader {
-    pub fn make(elf_data: &[u8]) -> Option<Self> {
-        let identity = match ELFIdentity::make(elf_data) {
-            Some(ident) => ident,
-            None => {
-                error!("Failed to make ELF identity struct");
-                return None;
+impl LoadedKernel {
+    /// Gives the image and symbol pages back, for when a boot entry is abandoned after loading
+    pub fn free(&self) {
+        unsafe {
+            let _ = boot::free_pages(NonNull::new_unchecked(self.physical_base as *mut u8), self.page_count);
+            if let Some(symbols) = &self.symbols {
+                let _ = boot::free_pages(NonNull::new_unchecked(symbols.symtab.as_ptr() as *mut u8), symbols.page_count);
             }
-        };
-
-        let (e_type, isa, version) = match Self::make_first_three(elf_data) {
-            Some(three) => three,
-            None => return None,
-        };
+        }
+    }
 
-        let entry_function = u64::from_le_bytes(elf_data[24..32].try_into().unwrap()) as usize;
-        let program_header_offset = u64::from_le_bytes(elf_data[32..40].try_into().unwrap()) as usize;
-        let header_size = u16::from_le_bytes(elf_data[52..54].try_into().unwrap());
-        let phentry_size = u16::from_le_bytes(elf_data[54..56].try_into().unwrap());
-        let phentry_amount = u16::from_le_bytes(elf_data[56..58].try_into().unwrap());
+    /// Allocates `size` bytes of zeroed LOADER_DATA pages for the kernel stack, placed above the image and its guard page
+    pub fn allocate_stack(&self, size: usize) -> Result<KernelStack, Status> {
+        let page_count = size.div_ceil(PAGE_SIZE).max(1);
+        let virtual_base = (self.page_count + 1).checked_mul(PAGE_SIZE)
+            .and_then(|offset| self.virtual_base.checked_add(offset))
+            .filter(|base| base.checked_add(page_count * PAGE_SIZE).is_some())
+            .ok_or(Status::BAD_BUFFER_SIZE)?;
+
+        let pages = boot::allocate_pages(AllocateType::AnyPages, MemoryType::LOADER_DATA, page_count).map_err(|e| e.status())?;
+        unsafe {
+            core::ptr::write_bytes(pages.as_ptr(), 0, page_count * PAGE_SIZE);
+        }
+        info!("Kernel stack: virtual {:#x}, physical {:#x}, {} pages", virtual_base, pages.as_ptr() as usize, page_count);
 
-        Some(Self {
-            elf_identity: identity,
-            program_headers: Vec::new(),
-            e_type,
-            isa,
-            version,
-            entry_function,
-            program_header_offset,
-            flags: 0x0,
-            header_size,
-            phentry_size,
-            phentry_amount
-        })
+        Ok(KernelStack { virtual_base, physical_base: pages.as_ptr() as usize, page_count })
     }
+}
 
-    fn make_first_three(elf_data: &[u8]) -> Option<(ObjectFileType, ISA, u32)> {
-        let etype_integer = u16::from_le_bytes(elf_data[16..18].try_into().unwrap());
-        let e_type = match ObjectFileType::try_from_primitive(etype_integer) {
-            Ok(t) => t,
-            Err(e) => {
-                error!("Failed to convert elf_data[16..18] to ObjectFileType. Error: {}", e);
-                return None;
-            }
-        };
-
-        let emachine_integer = u16::from_le_bytes(elf_data[18..20].try_into().unwrap());
-        let isa = match ISA::try_from_primitive(emachine_integer) {
-            Ok(isa) => isa,
-            Err(e) => {
-                error!("Failed to convert elf_data[18..20] to ISA. Error: {}", e);
-                return None;
+/// Patches every relocation of a position-independent kernel in its physical image.
+/// `image` holds the kernel as linked at `linked_base`; it will run `bias` bytes higher.
+fn apply_relocations(relocations: &[Relocation], image: *mut u8, linked_base: usize, bias: usize) -> Result<(), LoadError> {
+    for relocation in relocations {
+        match relocation.r_type {
+            R_X86_64_NONE => {},
+            R_X86_64_RELATIVE => {
+                let value = (relocation.addend as usize).wrapping_add(bias);
+                // Targets were checked against the PT_LOAD segments when the ELF was parsed
+                unsafe {
+                    let target = image.add(relocation.offset - linked_base) as *mut u64;
+                    target.write_unaligned(value as u64);
+                }
+            },
+            r_type => {
+                error!("Kernel has an unsupported relocation of type {} at {:#x}", r_type, relocation.offset);
+                return Err(LoadError::UnsupportedRelocation { r_type, offset: relocation.offset });
             }
-        };
-
-        let version = u32::from_le_bytes(elf_data[20..24].try_into().unwrap());
-
-        Some((e_type, isa, version))
+        }
     }
-}
 
-pub struct ELFIdentity {
-    pub magic: [u8; 4],
-    pub class: ELFClass,
-    pub endianness: Endianness,
-    pub ei_version: u8,
-    pub os_abi: OSABI,
-    pub abi_version: u8, // Mostly unused. Can ignore but keep as a field.
+    Ok(())
 }
 
-impl ELFIdentity {
-    pub fn make(elf_data: &[u8]) -> Option<Self> {
-        let magic_slice = &elf_data[0..4];
-        if magic_slice != b"\x7FELF" {
-            error!("Not a valid ELF. Provided data does not contain the ELF magic number.");
-            return None;
-        }
-
-        let mut magic = [0u8; 4];
-        let mut index = 0;
-        for b in magic_slice {
-            magic[index] = *b;
-            index += 1;
+/// Copies the PT_LOAD segments of an already validated ELF into freshly allocated LOADER_DATA pages.
+/// Position-independent kernels are relocated to a randomized base in the higher half.
+pub fn load_kernel(elf: &ELFHeader, data: &[u8]) -> Result<LoadedKernel, LoadError> {
+    // validate_segments guarantees at least one PT_LOAD
+    let linked_base = elf.load_segments().map(|ph| ph.aligned_vaddr).min().ok_or(ElfError::NoLoadableSegments)?;
+    let virtual_end = elf.load_segments().map(|ph| ph.vaddr_end).max().ok_or(ElfError::NoLoadableSegments)?;
+    let page_count = (virtual_end - linked_base) / PAGE_SIZE;
+
+    let virtual_base = if elf.is_position_independent() {
+        kaslr::randomize_base(linked_base, page_count * PAGE_SIZE)
+    } else {
+        linked_base
+    };
+    let bias = virtual_base - linked_base;
+
+    // The kernel is linked in the higher half, so its physical home can be anywhere the firmware has room
+    let pages = match boot::allocate_pages(AllocateType::AnyPages, MemoryType::LOADER_DATA, page_count) {
+        Ok(pages) => pages,
+        Err(e) => {
+            error!("UEFI failed to allocate {} pages for the kernel image! Error: {}", page_count, e);
+            return Err(LoadError::AllocationFailed(e.status()));
         }
-
-        let class = match ELFClass::try_from_primitive(elf_data[4]) {
-            Ok(c) => c,
-            Err(e) => {
-                error!("Failed to convert elf_data[5] into ELFClass enum. Error: {}", e);
-                return None;
-            }
-        };
-        let endianness = match Endianness::try_from_primitive(elf_data[5]) {
-            Ok(end) => end,
-            Err(e) => {
-                error!("Failed to convert elf_data[6] into Endianness enum. Error: {}", e);
-                return None;
-            }
-        };
-
-        let os_abi = match OSABI::try_from_primitive(elf_data[7]) {
-            Ok(osabi) => osabi,
-            Err(e) => {
-                error!("Failed to convert elf_data[8] into OSABI enum. Error: {}", e);
-                return None;
+    };
+    let image = pages.as_ptr();
+    unsafe {
+        core::ptr::write_bytes(image, 0, page_count * PAGE_SIZE);
+    }
+    info!("Kernel image: virtual {:#x}, physical {:#x}, {} pages", virtual_base, image as usize, page_count);
+
+    let mut segments = Vec::new();
+    let load_segments = elf.program_headers.iter().enumerate().filter(|(_, ph)| ph.p_type == ProgramHeaderType::Load);
+    for (index, ph) in load_segments {
+        debug!(
+            "Segment @ p_vaddr={:#x}, mem_start={:#x}, filesz={:#x}, memsz={:#x}",
+            ph.non_aligned_vaddr, ph.aligned_vaddr, ph.file_size, ph.memory_size
+        );
+
+        // The image was zeroed above, which also takes care of .bss (p_memsz > p_filesz)
+        let Some(src) = ph.file_bytes(data) else {
+            unsafe {
+                let _ = boot::free_pages(pages, page_count);
             }
+            return Err(ElfError::SegmentOutOfBounds(index).into());
         };
+        unsafe {
+            let dst_ptr = image.add(ph.non_aligned_vaddr - linked_base);
+            core::ptr::copy_nonoverlapping(src.as_ptr(), dst_ptr, src.len());
+        }
 
-        let ei_version = elf_data[7];
-        let abi_version = elf_data[9];
-
-        Some(Self {
-            magic,
-            class,
-            endianness,
-            os_abi,
-            ei_version,
-            abi_version
-        })
+        segments.push(KernelSegment {
+            virt_start: ph.aligned_vaddr + bias,
+            page_count: ph.page_count,
+            writable: ph.flags & PF_W != 0,
+        });
     }
-}
-
-pub struct ProgramHeader {
-    pub p_type: ProgramHeaderType,
-    pub offset: usize,
-    pub aligned_vaddr: usize,
-    pub non_aligned_vaddr: usize,
-    pub physical_addr: usize,
-    pub file_size: usize,
-    pub memory_size: usize,
-    pub vaddr_end: usize,
-    pub page_count: usize,
-}
-
-impl ProgramHeader {
-    pub fn new(elf_data: &[u8], ph_offset: usize, phentry_size: u16, index: usize) -> Option<Self> {
-        let p_type = Self::get_type(elf_data, ph_offset, phentry_size, index);
-        if let ProgramHeaderType::Load = p_type {
-            let offset = u64::from_le_bytes(elf_data[ph_offset + 8..ph_offset + 16].try_into().unwrap()) as usize;
-            let non_vaddr = u64::from_le_bytes(elf_data[ph_offset + 16..ph_offset + 24].try_into().unwrap()) as usize;
-            let physical_addr = u64::from_le_bytes(elf_data[ph_offset + 24..ph_offset + 32].try_into().unwrap()) as usize;
-            let file_size = u64::from_le_bytes(elf_data[ph_offset + 32..ph_offset + 40].try_into().unwrap()) as usize;
-            let memory_size = u64::from_le_bytes(elf_data[ph_offset + 40..ph_offset + 48].try_into().unwrap()) as usize;
 
-            // Alignment
-            let aligned_vaddr = non_vaddr & !(PAGE_SIZE - 1);
-            let vaddr_end = (non_vaddr + memory_size + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
-            let page_count = (vaddr_end - aligned_vaddr) / PAGE_SIZE;
-            
-            return Some(Self {
-                p_type,
-                offset,
-                aligned_vaddr: aligned_vaddr,
-                non_aligned_vaddr: physical_addr,
-                physical_addr,
-                file_size,
-                memory_size,
-                vaddr_end,
-                page_count
-            });
+    if elf.is_position_independent() {
+        if let Err(e) = apply_relocations(&elf.relocations, image, linked_base, bias) {
+            // Give the pages back so a fallback boot entry has room
+            unsafe {
+                let _ = boot::free_pages(pages, page_count);
+            }
+            return Err(e);
         }
-;
-        None
+        info!("Applied {} relocations, kernel linked at {:#x} slid by {:#x}", elf.relocations.len(), linked_base, bias);
     }
 
-    pub fn get_type(elf_data: &[u8], ph_offset: usize, phentry_size: u16, index: usize) -> ProgramHeaderType {
-        let ph_offset = ph_offset + index * phentry_size as usize;
-        let p_type = u32::from_le_bytes(elf_data[ph_offset..ph_offset + 4].try_into().unwrap());
-        let p_type = match ProgramHeaderType::try_from_primitive(p_type) {
-            Ok(t) => t,
-            Err(e) => {
-                error!("Failed to convert p_type to ProgramHeaderType enum. Error: {}", e);
-                return ProgramHeaderType::Null;
-            }
-        };
+    Ok(LoadedKernel {
+        entry: elf.entry_function + bias,
+        physical_base: image as usize,
+        virtual_base,
+        page_count,
+        segments,
+        symbols: load_symbols(elf, data),
+    })
+}
 
-        p_type
+/// Copies the symbol table out of the file so the kernel can symbolize addresses.
+/// Symbols are a debugging aid, so a stripped or odd kernel still boots without them.
+fn load_symbols(elf: &ELFHeader, data: &[u8]) -> Option<KernelSymbols> {
+    let table = match elf.symbol_table(data) {
+        Ok(Some(table)) => table,
+        Ok(None) => {
+            info!("Kernel has no symbol table");
+            return None;
+        },
+        Err(e) => {
+            warn!("Kernel symbol table is unusable: {:?}", e);
+            return None;
+        }
+    };
+
+    let (symtab, strtab) = (table.symbols(), table.strings());
+    let page_count = (symtab.len() + strtab.len()).div_ceil(PAGE_SIZE).max(1);
+    let pages = match boot::allocate_pages(AllocateType::AnyPages, MemoryType::LOADER_DATA, page_count) {
+        Ok(pages) => pages,
+        Err(e) => {
+            warn!("Failed to allocate {} pages for the kernel symbols! Error: {}", page_count, e);
+            return None;
+        }
+    };
+
+    unsafe {
+        let base = pages.as_ptr();
+        core::ptr::copy_nonoverlapping(symtab.as_ptr(), base, symtab.len());
+        core::ptr::copy_nonoverlapping(strtab.as_ptr(), base.add(symtab.len()), strtab.len());
+        info!("Copied {} bytes of kernel symbols to {:#x}", symtab.len() + strtab.len(), base as usize);
+
+        Some(KernelSymbols {
+            symtab: core::slice::from_raw_parts(base, symtab.len()),
+            strtab: core::slice::from_raw_parts(base.add(symtab.len()), strtab.len()),
+            page_count,
+        })
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,87 @@
+use log::*;
+use uefi::boot::{self, MemoryType};
+use uefi::mem::memory_map::{MemoryAttribute, MemoryMap, MemoryMapMut, MemoryMapOwned};
+use uefi::table::cfg::{ACPI2_GUID, ACPI_GUID, SMBIOS3_GUID, SMBIOS_GUID};
+use uefi::{system, table, Guid, Status};
+
+use crate::boot_error::BootError;
+
+pub struct FirmwareTables {
+    pub acpi_rsdp: u64,
+    pub smbios_entry: u64,
+    pub runtime_services: u64,
+}
+
+fn config_table(guid: Guid) -> Option<u64> {
+    system::with_config_table(|entries| {
+        entries.iter()
+            .find(|entry| entry.guid == guid)
+            .map(|entry| entry.address as u64)
+    })
+}
+
+/// Collects the tables the kernel needs from the system table. Must run before exit_boot_services.
+pub fn find_tables() -> FirmwareTables {
+    let acpi_rsdp = config_table(ACPI2_GUID).or_else(|| config_table(ACPI_GUID)).unwrap_or(0);
+    let smbios_entry = config_table(SMBIOS3_GUID).or_else(|| config_table(SMBIOS_GUID)).unwrap_or(0);
+    let runtime_services = match table::system_table_raw() {
+        Some(st) => unsafe { st.as_ref().runtime_services as u64 },
+        None => 0,
+    };
+
+    info!("ACPI RSDP at {:#x}, SMBIOS entry point at {:#x}, runtime services at {:#x}",
+        acpi_rsdp, smbios_entry, runtime_services);
+    if acpi_rsdp == 0 {
+        warn!("Firmware has no ACPI tables");
+    }
+
+    FirmwareTables { acpi_rsdp, smbios_entry, runtime_services }
+}
+
+/// Switches the runtime services to virtual mode, mapping every runtime region at its physical address.
+/// The kernel identity maps all memory, so firmware pointers stay valid after the switch.
+///
+/// # Safety
+/// Boot services must have been exited, and `mmap` must be the map returned by exit_boot_services.
+pub unsafe fn enter_virtual_mode(mmap: &mut MemoryMapOwned) -> Status {
+    for index in 0..mmap.len() {
+        if let Some(desc) = mmap.get_mut(index)
+            && desc.att.contains(MemoryAttribute::RUNTIME) {
+            desc.virt_start = desc.phys_start;
+        }
+    }
+
+    let runtime_services = match table::system_table_raw() {
+        Some(st) => unsafe { &*st.as_ref().runtime_services },
+        None => return Status::UNSUPPORTED,
+    };
+
+    // The map's stride is the firmware's descriptor size, so call the service directly
+    // instead of going through a slice of uefi's MemoryDescriptor
+    let meta = mmap.meta();
+    unsafe {
+        let buffer = mmap.buffer_mut();
+        (runtime_services.set_virtual_address_map)(
+            meta.entry_count() * meta.desc_size,
+            meta.desc_size,
+            meta.desc_version,
+            buffer.as_mut_ptr().cast(),
+        )
+    }
+}
+
+/// Exits boot services and returns the final memory map, which lives in LOADER_DATA pages.
+///
+/// The exit itself is uefi's exit_boot_services, which first shuts down the crate's own helpers, retries
+/// once with a fresh map key and resets the machine if that fails too. Before committing to it, the map is
+/// read once while boot services can still report a failure, and logging is turned off, since the console
+/// belongs to boot services.
+///
+/// # Safety
+/// Nothing may use boot services, or memory and protocols they own, after this returns Ok.
+pub unsafe fn exit_boot_services() -> Result<MemoryMapOwned, BootError> {
+    boot::memory_map(MemoryType::LOADER_DATA).map_err(|e| BootError::MemoryMap(e.status()))?;
+
+    log::set_max_level(LevelFilter::Off);
+    Ok(unsafe { boot::exit_boot_services(Some(MemoryType::LOADER_DATA)) })
+}
\ No newline at end of file
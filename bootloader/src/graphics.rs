@@ -0,0 +1,123 @@
+use boot_protocol::{FramebufferInfo, PIXEL_BGR, PIXEL_BITMASK, PIXEL_RGB};
+use formats::config::VideoMode;
+use formats::edid;
+use log::*;
+use uefi::boot::{self, OpenProtocolAttributes, OpenProtocolParams};
+use uefi::proto::console::gop::{GraphicsOutput, Mode, PixelFormat};
+use uefi::proto::unsafe_protocol;
+use uefi::Handle;
+
+/// EFI_EDID_ACTIVE_PROTOCOL, the EDID of the display the GOP is currently driving
+#[repr(C)]
+#[unsafe_protocol("bd8c1056-9f36-44ec-92a8-a6337f817986")]
+struct EdidActive {
+    size: u32,
+    edid: *const u8,
+}
+
+/// The display's preferred resolution, if the firmware exposes its EDID on the GOP handle.
+fn native_resolution(gop_handle: Handle) -> Option<(usize, usize)> {
+    let params = OpenProtocolParams {
+        handle: gop_handle,
+        agent: boot::image_handle(),
+        controller: None,
+    };
+    // GetProtocol does not take the protocol away from the GOP driver
+    let edid_active = unsafe { boot::open_protocol::<EdidActive>(params, OpenProtocolAttributes::GetProtocol) };
+    let edid_active = match edid_active {
+        Ok(e) => e,
+        Err(e) => {
+            warn!("The display has no EDID ({}), its native resolution is unknown", e.status());
+            return None;
+        }
+    };
+    if edid_active.edid.is_null() {
+        return None;
+    }
+
+    let data = unsafe { core::slice::from_raw_parts(edid_active.edid, edid_active.size as usize) };
+    match edid::preferred_resolution(data) {
+        Ok(resolution) => Some(resolution),
+        Err(e) => {
+            warn!("Could not read the preferred timing from the EDID! Error: {:?}", e);
+            None
+        }
+    }
+}
+
+/// Modes the kernel can draw to. Blt-only modes have no linear framebuffer.
+fn drawable_modes(gop: &GraphicsOutput) -> impl Iterator<Item = Mode> + '_ {
+    gop.modes().filter(|mode| mode.info().pixel_format() != PixelFormat::BltOnly)
+}
+
+/// Switches to the configured mode. If it cannot be found or set, the firmware's mode is kept.
+pub fn select_mode(gop: &mut GraphicsOutput, gop_handle: Handle, video_mode: VideoMode) {
+    for (index, mode) in gop.modes().enumerate() {
+        let info = mode.info();
+        debug!("Graphics mode {}: {}x{} {:?}", index, info.resolution().0, info.resolution().1, info.pixel_format());
+    }
+
+    let resolution = match video_mode {
+        VideoMode::Current => return,
+        VideoMode::Resolution(width, height) => (width, height),
+        VideoMode::Native => match native_resolution(gop_handle) {
+            Some(resolution) => resolution,
+            None => {
+                warn!("Keeping the current graphics mode");
+                return;
+            }
+        },
+        VideoMode::Largest => {
+            match drawable_modes(gop).map(|mode| mode.info().resolution()).max_by_key(|(width, height)| width * height) {
+                Some(resolution) => resolution,
+                None => {
+                    warn!("No graphics mode has a framebuffer, keeping the current one");
+                    return;
+                }
+            }
+        },
+    };
+
+    if gop.current_mode_info().resolution() == resolution {
+        info!("Already at {}x{}", resolution.0, resolution.1);
+        return;
+    }
+
+    let mode = drawable_modes(gop).find(|mode| mode.info().resolution() == resolution);
+    match mode {
+        Some(mode) => match gop.set_mode(&mode) {
+            Ok(()) => info!("Switched to {}x{}", resolution.0, resolution.1),
+            Err(e) => error!("Failed to switch to {}x{}! Error: {}", resolution.0, resolution.1, e),
+        },
+        None => warn!("No graphics mode is {}x{}, keeping the current one", resolution.0, resolution.1),
+    }
+}
+
+/// Describes the current mode's framebuffer for the kernel. None in a Blt-only mode.
+pub fn framebuffer_info(gop: &mut GraphicsOutput) -> Option<FramebufferInfo> {
+    let mode_info = gop.current_mode_info();
+    let (pixel_format, red_mask, green_mask, blue_mask, reserved_mask) = match mode_info.pixel_format() {
+        // Byte order in memory, so red is the low byte for RGB
+        PixelFormat::Rgb => (PIXEL_RGB, 0x0000ff, 0x00ff00, 0xff0000, 0xff000000),
+        PixelFormat::Bgr => (PIXEL_BGR, 0xff0000, 0x00ff00, 0x0000ff, 0xff000000),
+        PixelFormat::Bitmask => {
+            let masks = mode_info.pixel_bitmask()?;
+            (PIXEL_BITMASK, masks.red, masks.green, masks.blue, masks.reserved)
+        },
+        PixelFormat::BltOnly => return None,
+    };
+
+    let mut fb = gop.frame_buffer();
+    Some(FramebufferInfo {
+        base: fb.as_mut_ptr() as *mut u32,
+        size: fb.size(),
+        width: mode_info.resolution().0,
+        height: mode_info.resolution().1,
+        pixels_per_scan_line: mode_info.stride(),
+        pixel_format,
+        red_mask,
+        green_mask,
+        blue_mask,
+        reserved_mask,
+    })
+}
\ No newline at end of file
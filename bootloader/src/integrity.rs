@@ -0,0 +1,72 @@
+use alloc::format;
+use core::fmt::Write;
+
+use formats::config::BootEntry;
+use formats::sha256::{self, HexDigest, DIGEST_SIZE};
+use log::*;
+use uefi::proto::console::text::Color;
+use uefi::proto::media::file::Directory;
+use uefi::system;
+
+use crate::dir_management::read_file;
+
+/// Digest file next to the kernel, in `sha256sum` format, used when the config has no kernel_sha256
+pub const DIGEST_SUFFIX: &str = ".sha256";
+
+/// The digest the kernel must have: kernel_sha256 from the config, otherwise `<kernel>.sha256`.
+/// None means the entry does not ask for a check.
+fn expected_digest(dir: &mut Directory, entry: &BootEntry) -> Result<Option<[u8; DIGEST_SIZE]>, ()> {
+    if let Some(digest) = entry.kernel_sha256 {
+        return Ok(Some(digest));
+    }
+
+    let path = format!("{}{}", entry.kernel_path, DIGEST_SUFFIX);
+    let data = match read_file(dir, &path) {
+        Some(data) => data,
+        None => return Ok(None),
+    };
+
+    match core::str::from_utf8(&data).ok().and_then(sha256::parse_hex_digest) {
+        Some(digest) => Ok(Some(digest)),
+        None => {
+            error!("{} does not hold a SHA-256 digest", path);
+            Err(())
+        }
+    }
+}
+
+/// Checks the kernel file against its expected digest. Returns false, after telling the user why,
+/// if the entry must not boot: the digest does not match or the digest file is unreadable.
+pub fn verify_kernel(dir: &mut Directory, entry: &BootEntry, kernel: &[u8]) -> bool {
+    let expected = match expected_digest(dir, entry) {
+        Ok(Some(digest)) => digest,
+        Ok(None) => {
+            info!("No digest for {}, skipping the integrity check", entry.kernel_path);
+            return true;
+        },
+        Err(()) => {
+            refuse(entry, "its digest file is malformed");
+            return false;
+        }
+    };
+
+    let actual = sha256::digest(kernel);
+    if actual != expected {
+        error!("{} has SHA-256 {}, expected {}", entry.kernel_path, HexDigest(&actual), HexDigest(&expected));
+        refuse(entry, "it does not match its SHA-256 digest. It may be damaged or only partly copied.");
+        return false;
+    }
+
+    info!("{} matches its SHA-256 digest", entry.kernel_path);
+    true
+}
+
+/// Shown on the console whatever the log level, since the next entry may boot right after
+fn refuse(entry: &BootEntry, reason: &str) {
+    system::with_stdout(|out| {
+        let _ = out.set_color(Color::White, Color::Red);
+        let _ = write!(out, "Refusing to boot {} ({}): {}", entry.title, entry.kernel_path, reason);
+        let _ = out.set_color(Color::LightGray, Color::Black);
+        let _ = writeln!(out);
+    });
+}
\ No newline at end of file
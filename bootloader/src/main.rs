@@ -1,188 +1,353 @@
 #![no_main]
 #![no_std]
 
+mod boot_error;
+mod boot_log;
+mod config;
 mod dir_management;
-mod utils;
 mod elf_loading;
+mod firmware;
+mod graphics;
+mod integrity;
+mod menu;
+mod paging;
+mod timing;
 
 extern crate alloc;
 
-use alloc::{boxed::Box, vec::Vec};
+use alloc::{boxed::Box, format, vec::Vec};
+use boot_error::BootError;
+use core::arch::asm;
+use core::convert::Infallible;
 use dir_management::*;
+use elf_loading::{KernelStack, LoadError, LoadedKernel};
+use formats::config::{BootConfig, BootEntry};
+use formats::elf::ELFHeader;
+use paging::PageTableBuilder;
 
 use log::*;
-use uefi::{boot::{open_protocol_exclusive, MemoryDescriptor, MemoryType, PAGE_SIZE}, mem::memory_map::MemoryMap, prelude::*, proto::{console::gop::GraphicsOutput, media::file::File}};
+use uefi::{boot::{open_protocol_exclusive, MemoryDescriptor, MemoryType, PAGE_SIZE}, mem::memory_map::MemoryMap, prelude::*, Guid, proto::{console::gop::GraphicsOutput, media::file::Directory}};
 use linked_list_allocator::LockedHeap;
 
-use crate::utils::framebuffer::FramebufferInfo;
+use boot_protocol::BootInfo;
 
 #[global_allocator]
 static ALLOCATOR: LockedHeap = LockedHeap::empty();
 
+// LOADER_DATA pool memory, so boxes handed to the kernel stay valid after exit_boot_services
+const HEAP_SIZE: usize = 1024 * 1024;
+
 #[entry]
 fn main() -> Status {
-    uefi::helpers::init().unwrap();
-    info!("Begin boot process");
-    info!("Initializing heap");
+    if let Err(e) = uefi::helpers::init() {
+        return e.status();
+    }
 
-    let mut heap_space = [0u8; 1024 * 100];
+    // The logger buffers records for the log file, so the heap comes first
+    let heap = match boot::allocate_pool(MemoryType::LOADER_DATA, HEAP_SIZE) {
+        Ok(heap) => heap,
+        Err(e) => return boot_error::report(&BootError::Heap(e.status()), None),
+    };
     unsafe {
-        ALLOCATOR.lock().init(heap_space.as_mut_ptr(), heap_space.len());
+        ALLOCATOR.lock().init(heap.as_ptr(), HEAP_SIZE);
     }
+    // The TSC counts from reset, so the first phase covers the firmware too
+    timing::mark("firmware");
+    boot_log::init();
+    info!("Begin boot process");
     info!("Initialized heap. Dynamic memory allocation via alloc is now available");
 
+    let config = config::load_config();
+    log::set_max_level(config::level_filter(config.log_level));
+    info!("Config: {} boot entries on volume {}", config.entries.len(), config.volume_label);
+    timing::mark("config");
+
+    // boot only comes back on failure, by which point everything it allocated has been dropped
+    let error = match boot(&config) {
+        Ok(never) => match never {},
+        Err(e) => e,
+    };
+    let status = boot_error::report(&error, Some(&config));
+    drop(config);
+    unsafe {
+        let _ = boot::free_pool(heap);
+    }
+    status
+}
+
+fn boot(config: &BootConfig) -> Result<Infallible, BootError> {
+
     info!("Finding an SFS to find the kernel binary");
-    let mut sfs_dir = match find_kernel_volume() {
-        Some(sfs) => sfs,
-        None => {
-            error!("An unhandled error occurred when trying to open the kernel volume!");
-            return Status::LOAD_ERROR;
+    let mut sfs_dir = find_kernel_volume(config).ok_or_else(|| BootError::NoKernelVolume(match config.volume_guid {
+        Some(guid) => format!("has partition GUID {}", Guid::from_bytes(guid)),
+        None => format!("is labelled {} or holds a kernel", config.volume_label),
+    }))?;
+    timing::mark("volume");
+
+    // Try the chosen entry first and fall through to the next ones if it does not load
+    let mut loaded = None;
+    let choices = menu::choose(config);
+    // Includes however long the menu waited for the user
+    timing::mark("menu");
+    for entry in choices {
+        info!("Trying boot entry {}: {} {}", entry.title, entry.kernel_path, entry.cmdline);
+        match load_entry(&mut sfs_dir, &entry) {
+            Ok((kernel, initrd)) => {
+                loaded = Some((entry, kernel, initrd));
+                break;
+            },
+            Err(status) => warn!("Boot entry {} failed ({}), trying the next one", entry.title, status),
         }
-    };
+    }
+    let (entry, kernel, initrd) = loaded.ok_or(BootError::NoBootableEntry)?;
+    let stack = kernel.allocate_stack(config.stack_size).map_err(BootError::KernelStack)?;
+
+    let gop_handle = boot::get_handle_for_protocol::<GraphicsOutput>().map_err(|e| BootError::Graphics(e.status()))?;
+    let mut gop = open_protocol_exclusive::<GraphicsOutput>(gop_handle).map_err(|e| BootError::Graphics(e.status()))?;
+    graphics::select_mode(&mut gop, gop_handle, config.video_mode);
+    let fb_info = graphics::framebuffer_info(&mut gop).ok_or(BootError::NoFramebuffer)?;
+    let fb_size = fb_info.size;
+    info!("Framebuffer: {}x{}, format {}, masks r {:#x} g {:#x} b {:#x}",
+        fb_info.width, fb_info.height, fb_info.pixel_format, fb_info.red_mask, fb_info.green_mask, fb_info.blue_mask);
+    timing::mark("gop");
 
-    info!("Reading kernel binary");
-    info!("TEST INFO");
-    if sfs_dir.is_directory().unwrap() {
-        info!("SFS Dir is a directory");
+    let fb_info_box = Box::new(fb_info);
+    let fb_info_raw = Box::into_raw(fb_info_box);
+
+    let mut boot_info = BootInfo::new(fb_info_raw);
+    boot_info.kernel_physical_base = kernel.physical_base as u64;
+    boot_info.kernel_virtual_base = kernel.virtual_base as u64;
+    boot_info.kernel_size = (kernel.page_count * PAGE_SIZE) as u64;
+    if let Some(cmdline) = copy_cmdline(&entry.cmdline) {
+        boot_info.cmdline = cmdline;
+        boot_info.cmdline_len = entry.cmdline.len();
+    }
+    if let Some(initrd) = initrd {
+        boot_info.initrd_base = initrd.as_ptr() as u64;
+        boot_info.initrd_size = initrd.len() as u64;
+    }
+    let tables = firmware::find_tables();
+    boot_info.acpi_rsdp = tables.acpi_rsdp;
+    boot_info.smbios_entry = tables.smbios_entry;
+    boot_info.runtime_services = tables.runtime_services;
+    if let Some(symbols) = &kernel.symbols {
+        boot_info.symtab_base = symbols.symtab.as_ptr() as u64;
+        boot_info.symtab_size = symbols.symtab.len() as u64;
+        boot_info.strtab_base = symbols.strtab.as_ptr() as u64;
+        boot_info.strtab_size = symbols.strtab.len() as u64;
+    }
+    boot_info.stack_virtual_base = stack.virtual_base as u64;
+    boot_info.stack_physical_base = stack.physical_base as u64;
+    boot_info.stack_size = (stack.page_count * PAGE_SIZE) as u64;
+    boot_info.tsc_frequency = timing::tsc_frequency();
+    timing::mark("tsc calibration");
+    let boot_info_raw = Box::into_raw(Box::new(boot_info));
+
+    // Identity map the memory the bootloader still runs in and the handoff points into, plus the framebuffer
+    let framebuffer_base = unsafe { (*fb_info_raw).base as u64 };
+    let ranges = identity_ranges(
+        &boot::memory_map(MemoryType::LOADER_DATA).map_err(|e| BootError::MemoryMap(e.status()))?,
+        framebuffer_base,
+        fb_size as u64,
+    );
+    let page_tables = build_page_tables(&kernel, &stack, &ranges).ok_or(BootError::PageTables)?;
+    timing::mark("page tables");
+
+    info!("Booting");
+    info!("Exiting UEFI Boot Services");
+    boot_log::save(&mut sfs_dir);
+    // The GOP protocol belongs to boot services, so close it while they are still running
+    drop(gop);
+    let mut final_mmap = unsafe { firmware::exit_boot_services()? };
+
+    // If this fails the runtime services simply stay in physical mode, which the identity map also covers
+    let _ = unsafe { firmware::enter_virtual_mode(&mut final_mmap) };
+
+    // The map lives in LOADER_DATA pages, which the kernel keeps
+    let mmap_meta = final_mmap.meta();
+    let boot_info = unsafe { &mut *boot_info_raw };
+    boot_info.memory_map.descriptors = final_mmap.buffer().as_ptr();
+    boot_info.memory_map.descriptor_count = mmap_meta.entry_count();
+    boot_info.memory_map.descriptor_size = mmap_meta.desc_size;
+    boot_info.memory_map.descriptor_version = mmap_meta.desc_version;
+    timing::mark("exit boot srv");
+    let timestamps = timing::take();
+    boot_info.timestamps = timestamps.as_ptr();
+    boot_info.timestamp_count = timestamps.len();
+
+    // The identity map keeps this code, its stack and the boot info reachable across the switch
+    unsafe {
+        paging::switch_to(&page_tables);
+    }
+
+    // The firmware's stack is BOOT_SERVICES_DATA, which the kernel may hand out again, so never return to it.
+    // RBP is cleared to end frame pointer walks, and calling from the page-aligned top gives the SysV entry alignment.
+    unsafe {
+        asm!(
+            "mov rsp, {stack_top}",
+            "xor ebp, ebp",
+            "call {entry}",
+            "ud2",
+            stack_top = in(reg) stack.top(),
+            entry = in(reg) kernel.entry,
+            in("rdi") boot_info_raw,
+            options(noreturn),
+        );
     }
+}
 
-    let (kd, status) = open_kernel_elf(&mut sfs_dir);
+fn load_entry(dir: &mut Directory, entry: &BootEntry) -> Result<(LoadedKernel, Option<&'static [u8]>), Status> {
+    let (kd, status) = open_kernel_elf(dir, &entry.kernel_path);
     let kernel_data = match kd {
         Some(data) => {
             info!("Got kernel binary data from open_kernel_elf function");
             data
         },
         None => {
-            error!("An unhandled error occurred when reading the kernel binary. Cannot continue.");
-            return status;
+            error!("An unhandled error occurred when reading the kernel binary {}", entry.kernel_path);
+            return Err(status);
         },
     };
 
     let kernel_buffer = kernel_data.get_buffer_slice();
-    //let kernel_buffer = &kernel_buffer_slice[..kernel_data.len];
-    let entry = match parse_elf_and_load(kernel_buffer) {
-        Ok(e) => {
-            info!("Read kernel binary. Loading...");
-            e
-        },
-        Err(_) => {
-            error!("FATAL FAILED TO PARSE AND LOAD KERNEL BINARY! FAILED TO LOAD KERNEL!");
-            return Status::LOAD_ERROR;
-        },
+    timing::mark("kernel read");
+    let result = if integrity::verify_kernel(dir, entry, kernel_buffer) {
+        timing::mark("kernel verify");
+        parse_elf_and_load(kernel_buffer)
+    } else {
+        Err(LoadError::IntegrityCheckFailed)
     };
 
-    let gop_handle = boot::get_handle_for_protocol::<GraphicsOutput>().unwrap();
-    let mut gop = open_protocol_exclusive::<GraphicsOutput>(gop_handle).unwrap();
-    let mode_info = gop.current_mode_info();
-    let mut fb = gop.frame_buffer();
-    let fb_size = fb.size();
+    // The segments have been copied out, so the file contents are no longer needed either way
+    unsafe {
+        let _ = boot::free_pool(kernel_data.buffer);
+    }
 
-    let fb_slice = unsafe {
-        core::slice::from_raw_parts_mut(fb.as_mut_ptr() as *mut u32, fb_size / 4)
+    let kernel = match result {
+        Ok(k) => {
+            info!("Read kernel binary. Loading...");
+            timing::mark("elf load");
+            k
+        },
+        Err(e) => {
+            error!("FAILED TO PARSE AND LOAD KERNEL BINARY {}! Error: {}", entry.kernel_path, e);
+            return Err(match e {
+                LoadError::IntegrityCheckFailed => Status::SECURITY_VIOLATION,
+                _ => Status::LOAD_ERROR,
+            });
+        },
     };
 
-    let fb_info = FramebufferInfo {
-        buffer: fb_slice,
-        size: fb_size,
-        width: mode_info.resolution().0,
-        height: mode_info.resolution().1,
-        pixels_per_scan_line: mode_info.stride()
+    // A missing initrd fails the entry, so the menu can fall back to one that has everything it needs
+    let initrd = match &entry.initrd_path {
+        Some(path) => match load_initrd(dir, path) {
+            Some(initrd) => {
+                timing::mark("initrd");
+                Some(initrd)
+            },
+            None => {
+                error!("Failed to load initrd {}", path);
+                kernel.free();
+                return Err(Status::LOAD_ERROR);
+            }
+        },
+        None => None,
     };
 
-    let fb_info_box = Box::new(fb_info);
-    let fb_info_raw = Box::into_raw(fb_info_box);
+    Ok((kernel, initrd))
+}
 
-    info!("Booting");
-    info!("Exiting UEFI Boot Services");
-    unsafe {
-        _ = boot::exit_boot_services(None);
+fn parse_elf_and_load(data: &[u8]) -> Result<LoadedKernel, LoadError> {
+    // Only informational; a real shortage shows up as LoadError::AllocationFailed below
+    match boot::memory_map(MemoryType::LOADER_DATA) {
+        Ok(mmap) => match largest_conventional_region(mmap.entries().copied().collect()) {
+            Some(d) => {
+                info!("Largest conventional memory region: ");
+                info!("Start: {:#x},  Pages: {},  Type: {:?}", d.phys_start, d.page_count, d.ty);
+            },
+            None => warn!("No conventional memory is left for the kernel"),
+        },
+        Err(e) => warn!("Failed to get the memory map! Error: {}", e),
     }
 
-    let entry_fn: extern "sysv64" fn(*mut FramebufferInfo) -> ! = unsafe {
-        core::mem::transmute(entry)
-    };
-    entry_fn(fb_info_raw);
+    let elf = ELFHeader::make(data)?;
+    info!("Kernel ELF: {:?} {:?}, entry {:#x}, {} program headers",
+        elf.isa, elf.e_type, elf.entry_function, elf.program_headers.len());
+
+    elf_loading::load_kernel(&elf, data)
 }
 
-fn parse_elf_and_load(data: &[u8]) -> Result<usize, ()> {
-    const ELF_MAGIC: &[u8; 4] = b"\x7FELF";
-    if &data[0..4] != ELF_MAGIC {
-        error!("ELF FILE DOES NOT CONTAIN MAGIC HEADER. FAILED TO LOAD KERNEL");
-        return Err(());
+/// Copies the command line into LOADER_DATA pool memory, which the kernel never reclaims.
+fn copy_cmdline(cmdline: &str) -> Option<*const u8> {
+    if cmdline.is_empty() {
+        return None;
     }
 
-    let mmap = boot::memory_map(MemoryType::LOADER_DATA).expect("Failed to get memory map");
-    let mmap_iter = mmap.entries();
-    let mut mmap_vec = Vec::new();
-    for m in mmap_iter {
-        mmap_vec.push(*m);
+    match boot::allocate_pool(MemoryType::LOADER_DATA, cmdline.len()) {
+        Ok(pool) => unsafe {
+            core::ptr::copy_nonoverlapping(cmdline.as_ptr(), pool.as_ptr(), cmdline.len());
+            Some(pool.as_ptr() as *const u8)
+        },
+        Err(e) => {
+            error!("Failed to allocate the kernel command line! Error: {}", e);
+            None
+        }
     }
+}
 
-    match largest_conventional_region(mmap_vec) {
-        Some(d) => {
-            info!("Largest conventional memory region: ");
-            info!("Start: {:#x},  Pages: {},  Type: {:?}", d.phys_start, d.page_count, d.ty);
-        },
-        None => todo!(),
-    };
+/// The physical ranges to identity map, as (start, end, flags): loader, RAM and runtime memory, the ACPI
+/// tables the handoff points to, and uncached MMIO. Neighbouring ranges with the same flags are merged.
+fn identity_ranges(mmap: &impl MemoryMap, framebuffer_base: u64, framebuffer_size: u64) -> Vec<(u64, u64, u64)> {
+    let mut ranges: Vec<(u64, u64, u64)> = mmap.entries()
+        .filter_map(|desc| {
+            let flags = match desc.ty {
+                MemoryType::LOADER_CODE | MemoryType::LOADER_DATA
+                | MemoryType::BOOT_SERVICES_CODE | MemoryType::BOOT_SERVICES_DATA
+                | MemoryType::RUNTIME_SERVICES_CODE | MemoryType::RUNTIME_SERVICES_DATA
+                | MemoryType::CONVENTIONAL | MemoryType::ACPI_RECLAIM | MemoryType::ACPI_NON_VOLATILE => paging::WRITABLE,
+                MemoryType::MMIO => paging::MMIO,
+                _ => return None,
+            };
+            Some((desc.phys_start, desc.phys_start + desc.page_count * PAGE_SIZE as u64, flags))
+        })
+        .collect();
+    ranges.sort_unstable_by_key(|range| range.0);
 
-    let mut allocated_regions = alloc::collections::BTreeSet::new();
+    let mut merged: Vec<(u64, u64, u64)> = Vec::with_capacity(ranges.len() + 1);
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if last.1 == range.0 && last.2 == range.2 => last.1 = range.1,
+            _ => merged.push(range),
+        }
+    }
 
-    
-    let e_entry = u64::from_le_bytes(data[24..32].try_into().unwrap()) as usize;
-    let e_phoff = u64::from_le_bytes(data[32..40].try_into().unwrap()) as usize;
-    let e_phentsize = u16::from_le_bytes(data[54..56].try_into().unwrap()) as usize;
-    let e_phnum = u16::from_le_bytes(data[56..58].try_into().unwrap()) as usize;
+    // The framebuffer often sits above RAM, outside any descriptor
+    merged.push((framebuffer_base, framebuffer_base + framebuffer_size, paging::MMIO));
+    merged
+}
 
-    for i in 0..e_phnum {
-        let ph_offset = e_phoff + i * e_phentsize;
-        let p_type = u32::from_le_bytes(data[ph_offset..ph_offset + 4].try_into().unwrap());
-        if p_type != 1 {
-            continue;
-        }
+fn build_page_tables(kernel: &LoadedKernel, stack: &KernelStack, identity_ranges: &[(u64, u64, u64)]) -> Option<PageTableBuilder> {
+    let mut tables = PageTableBuilder::new()?;
+    for &(start, end, flags) in identity_ranges {
+        tables.identity_map(start, end, flags)?;
+    }
 
-        let p_offset = u64::from_le_bytes(data[ph_offset + 8..ph_offset + 16].try_into().unwrap()) as usize;
-        let p_vaddr = u64::from_le_bytes(data[ph_offset + 16..ph_offset + 24].try_into().unwrap()) as usize;
-        let p_filesz = u64::from_le_bytes(data[ph_offset + 32..ph_offset + 40].try_into().unwrap()) as usize;
-        let p_memsz = u64::from_le_bytes(data[ph_offset + 40..ph_offset + 48].try_into().unwrap()) as usize;
-
-        let mem_start = p_vaddr & !(PAGE_SIZE - 1);
-        let mem_end = (p_vaddr + p_memsz + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
-        let page_count = (mem_end - mem_start) / PAGE_SIZE;
-
-        let dst = if allocated_regions.contains(&mem_start) {
-            info!("Already allocated at {:#x}. Skipping.", mem_start);
-            mem_start as *mut u8
-        } else {
-            debug!(
-                "Segment @ p_vaddr={:#x}, mem_start={:#x}, pages={}, filesz={:#x}, memsz={:#x}",
-                p_vaddr, mem_start, page_count, p_filesz, p_memsz
-            );
-            let pages = uefi::boot::allocate_pages(
-            boot::AllocateType::Address(mem_start.try_into().unwrap()), 
-            MemoryType::LOADER_DATA, 
-            page_count).expect("UEFI failed to allocate pages"); // TODO: Fix cant find pages
-            allocated_regions.insert(mem_start);
-
-            pages.as_ptr()
-        };
-
-        let dst_ptr = unsafe { dst.add(p_vaddr - mem_start) };
-        unsafe {
-            core::ptr::copy_nonoverlapping(data.as_ptr().add(p_offset), dst_ptr, p_filesz);
+    for segment in &kernel.segments {
+        let flags = if segment.writable { paging::WRITABLE } else { 0 };
+        for page in 0..segment.page_count {
+            let virt = segment.virt_start + page * PAGE_SIZE;
+            let phys = kernel.physical_base + (virt - kernel.virtual_base);
+            tables.map_page(virt as u64, phys as u64, flags)?;
         }
+    }
 
-        if p_memsz > p_filesz {
-            unsafe {
-                core::ptr::write_bytes(
-                dst_ptr.add(p_filesz),
-                0,
-                p_memsz - p_filesz,
-                );
-            }
-        }
+    // The page between the image and the stack is never mapped
+    for page in 0..stack.page_count {
+        let offset = page * PAGE_SIZE;
+        tables.map_page((stack.virtual_base + offset) as u64, (stack.physical_base + offset) as u64, paging::WRITABLE)?;
     }
-    Ok(e_entry)
+
+    Some(tables)
 }
 
 fn largest_conventional_region(mmap: Vec<MemoryDescriptor>) -> Option<MemoryDescriptor> {
@@ -0,0 +1,136 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use formats::config::{BootConfig, BootEntry};
+use uefi::proto::console::text::{Color, Key, ScanCode};
+use uefi::{boot, system};
+
+const POLL_INTERVAL_US: usize = 50_000;
+const POLLS_PER_SECOND: u32 = (1_000_000 / POLL_INTERVAL_US) as u32;
+
+fn read_key() -> Option<Key> {
+    system::with_stdin(|stdin| stdin.read_key().ok().flatten())
+}
+
+fn wait_for_key() -> Key {
+    loop {
+        if let Some(key) = read_key() {
+            return key;
+        }
+        boot::stall(POLL_INTERVAL_US);
+    }
+}
+
+/// Waits up to `seconds` for a key press
+pub fn wait_for_key_timeout(seconds: u32) -> Option<Key> {
+    for _ in 0..seconds.saturating_mul(POLLS_PER_SECOND) {
+        if let Some(key) = read_key() {
+            return Some(key);
+        }
+        boot::stall(POLL_INTERVAL_US);
+    }
+
+    None
+}
+
+fn draw(entries: &[BootEntry], selected: usize, seconds_left: Option<u32>) {
+    system::with_stdout(|out| {
+        let _ = out.clear();
+        let _ = writeln!(out, "Sorix boot menu\n");
+        for (index, entry) in entries.iter().enumerate() {
+            if index == selected {
+                let _ = out.set_color(Color::Black, Color::LightGray);
+            }
+            let _ = write!(out, " {} ", entry.title);
+            let _ = out.set_color(Color::LightGray, Color::Black);
+            let _ = writeln!(out, "  {} {}", entry.kernel_path, entry.cmdline);
+        }
+
+        let _ = writeln!(out, "\nUp/Down: select   Enter: boot   e: edit command line");
+        if let Some(seconds) = seconds_left {
+            let _ = writeln!(out, "Booting {} in {}s", entries[selected].title, seconds);
+        }
+    });
+}
+
+/// Line editor for an entry's command line. Returns None if the edit was cancelled with Escape.
+fn edit_cmdline(entry: &BootEntry) -> Option<String> {
+    let mut cmdline = entry.cmdline.clone();
+    loop {
+        system::with_stdout(|out| {
+            let _ = out.clear();
+            let _ = writeln!(out, "Command line for {} (Enter: accept, Esc: cancel)\n", entry.title);
+            let _ = write!(out, "> {}_", cmdline);
+        });
+
+        match wait_for_key() {
+            Key::Special(ScanCode::ESCAPE) => return None,
+            Key::Printable(c) => match char::from(c) {
+                '\r' => return Some(cmdline),
+                '\u{8}' => {
+                    cmdline.pop();
+                },
+                c if !c.is_control() => cmdline.push(c),
+                _ => {},
+            },
+            _ => {},
+        }
+    }
+}
+
+/// Runs the menu until an entry is chosen or the countdown runs out. Any key stops the countdown.
+fn run_menu(entries: &mut [BootEntry], mut selected: usize, timeout: u32) -> usize {
+    let mut polls_left = Some(timeout.saturating_mul(POLLS_PER_SECOND));
+    draw(entries, selected, Some(timeout));
+
+    loop {
+        let key = match read_key() {
+            Some(key) => key,
+            None => {
+                if let Some(polls) = polls_left {
+                    if polls == 0 {
+                        return selected;
+                    }
+                    if polls % POLLS_PER_SECOND == 0 {
+                        draw(entries, selected, Some(polls / POLLS_PER_SECOND));
+                    }
+                    polls_left = Some(polls - 1);
+                }
+                boot::stall(POLL_INTERVAL_US);
+                continue;
+            }
+        };
+
+        polls_left = None;
+        match key {
+            Key::Special(ScanCode::UP) => selected = selected.checked_sub(1).unwrap_or(entries.len() - 1),
+            Key::Special(ScanCode::DOWN) => selected = (selected + 1) % entries.len(),
+            Key::Printable(c) if char::from(c) == '\r' => return selected,
+            Key::Printable(c) if char::from(c) == 'e' => {
+                if let Some(cmdline) = edit_cmdline(&entries[selected]) {
+                    entries[selected].cmdline = cmdline;
+                }
+            },
+            _ => {},
+        }
+        draw(entries, selected, None);
+    }
+}
+
+/// Lets the user pick a boot entry and returns the entries in the order they should be tried:
+/// the chosen one first, then the ones after it as fallbacks. A timeout of 0 skips the menu.
+pub fn choose(config: &BootConfig) -> Vec<BootEntry> {
+    let mut entries = config.entries.clone();
+    let mut selected = config.default_entry;
+
+    if config.timeout > 0 {
+        selected = run_menu(&mut entries, selected, config.timeout);
+        system::with_stdout(|out| {
+            let _ = out.clear();
+        });
+    }
+
+    entries.rotate_left(selected);
+    entries
+}
\ No newline at end of file
@@ -0,0 +1,113 @@
+use core::arch::asm;
+
+use log::*;
+use uefi::boot::{self, AllocateType, MemoryType, PAGE_SIZE};
+
+pub const PRESENT: u64 = 1 << 0;
+pub const WRITABLE: u64 = 1 << 1;
+const WRITE_THROUGH: u64 = 1 << 3;
+const CACHE_DISABLE: u64 = 1 << 4;
+const HUGE_PAGE: u64 = 1 << 7;
+
+const ADDRESS_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+const HUGE_PAGE_SIZE: usize = 0x200000; // 2 MiB
+
+pub const MMIO: u64 = WRITABLE | WRITE_THROUGH | CACHE_DISABLE;
+
+/// Builds the initial page tables the kernel starts on: an identity map of the physical ranges
+/// the bootloader and the handoff use, so it keeps running after the CR3 switch, plus the kernel's higher-half mapping.
+/// Every table lives in LOADER_DATA pages so it survives exit_boot_services.
+pub struct PageTableBuilder {
+    pml4: *mut u64,
+}
+
+fn allocate_table() -> Option<*mut u64> {
+    match boot::allocate_pages(AllocateType::AnyPages, MemoryType::LOADER_DATA, 1) {
+        Ok(page) => {
+            unsafe {
+                core::ptr::write_bytes(page.as_ptr(), 0, PAGE_SIZE);
+            }
+            Some(page.as_ptr() as *mut u64)
+        },
+        Err(e) => {
+            error!("Failed to allocate a page table! Error: {}", e);
+            None
+        }
+    }
+}
+
+impl PageTableBuilder {
+    pub fn new() -> Option<Self> {
+        Some(Self {
+            pml4: allocate_table()?,
+        })
+    }
+
+    pub fn pml4_address(&self) -> u64 {
+        self.pml4 as u64
+    }
+
+    fn next_table(table: *mut u64, index: usize) -> Option<*mut u64> {
+        let entry = unsafe { &mut *table.add(index) };
+        if *entry & PRESENT == 0 {
+            let next = allocate_table()?;
+            *entry = next as u64 | PRESENT | WRITABLE;
+        }
+
+        Some((*entry & ADDRESS_MASK) as *mut u64)
+    }
+
+    /// Walks down to the table at `depth` (1 = PDPT, 2 = PD, 3 = PT) covering `virt`, creating tables as needed.
+    fn table_for(&mut self, virt: u64, depth: usize) -> Option<*mut u64> {
+        let mut table = self.pml4;
+        for level in 0..depth {
+            let index = ((virt >> (39 - level * 9)) & 0x1FF) as usize;
+            table = Self::next_table(table, index)?;
+        }
+
+        Some(table)
+    }
+
+    pub fn map_page(&mut self, virt: u64, phys: u64, flags: u64) -> Option<()> {
+        let table = self.table_for(virt, 3)?;
+        let index = ((virt >> 12) & 0x1FF) as usize;
+        unsafe {
+            *table.add(index) = phys | flags | PRESENT;
+        }
+
+        Some(())
+    }
+
+    /// Identity maps `start..end` with `flags`, using 2 MiB pages wherever a whole aligned one fits.
+    /// Parts of the range that an earlier 2 MiB page already covers are left as they are.
+    pub fn identity_map(&mut self, start: u64, end: u64, flags: u64) -> Option<()> {
+        let mut address = start & !(PAGE_SIZE as u64 - 1);
+        while address < end {
+            let directory = self.table_for(address, 2)?;
+            let index = ((address >> 21) & 0x1FF) as usize;
+            let entry = unsafe { *directory.add(index) };
+
+            if entry & HUGE_PAGE != 0 {
+                address = (address | (HUGE_PAGE_SIZE as u64 - 1)) + 1;
+            } else if entry & PRESENT == 0 && address.is_multiple_of(HUGE_PAGE_SIZE as u64) && address + HUGE_PAGE_SIZE as u64 <= end {
+                unsafe {
+                    *directory.add(index) = address | flags | PRESENT | HUGE_PAGE;
+                }
+                address += HUGE_PAGE_SIZE as u64;
+            } else {
+                self.map_page(address, address, flags)?;
+                address += PAGE_SIZE as u64;
+            }
+        }
+
+        Some(())
+    }
+}
+
+/// # Safety
+/// The tables must map the code that is currently running, its stack and everything it touches next.
+pub unsafe fn switch_to(builder: &PageTableBuilder) {
+    unsafe {
+        asm!("mov cr3, {}", in(reg) builder.pml4_address(), options(nostack, preserves_flags));
+    }
+}
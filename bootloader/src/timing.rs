@@ -0,0 +1,43 @@
+use alloc::vec::Vec;
+use boot_protocol::BootTimestamp;
+use core::arch::x86_64::{__cpuid, _rdtsc};
+use log::*;
+use spin::Mutex;
+use uefi::boot;
+
+// Retried boot entries add more marks, so stop somewhere instead of growing the handoff forever
+const MAX_TIMESTAMPS: usize = 32;
+const CALIBRATION_MICROS: usize = 10_000;
+
+static TIMESTAMPS: Mutex<Vec<BootTimestamp>> = Mutex::new(Vec::new());
+
+/// Records that the phase `name` has just finished
+pub fn mark(name: &str) {
+    let tsc = unsafe { _rdtsc() };
+    let mut timestamps = TIMESTAMPS.lock();
+    if timestamps.len() < MAX_TIMESTAMPS {
+        timestamps.push(BootTimestamp::new(name, tsc));
+    }
+    debug!("Boot phase {} done at TSC {}", name, tsc);
+}
+
+/// Measures the TSC against the firmware's stall service. Returns 0 if the TSC did not move.
+pub fn tsc_frequency() -> u64 {
+    // CPUID.80000007H:EDX bit 8
+    if __cpuid(0x8000_0000).eax < 0x8000_0007 || __cpuid(0x8000_0007).edx & (1 << 8) == 0 {
+        warn!("The TSC is not invariant, boot phase timings may be off");
+    }
+
+    let start = unsafe { _rdtsc() };
+    boot::stall(CALIBRATION_MICROS);
+    let end = unsafe { _rdtsc() };
+
+    let frequency = end.saturating_sub(start) * (1_000_000 / CALIBRATION_MICROS as u64);
+    info!("TSC runs at {} kHz", frequency / 1000);
+    frequency
+}
+
+/// Hands the recorded timestamps over for good. They live on the LOADER_DATA heap, which the kernel keeps.
+pub fn take() -> &'static [BootTimestamp] {
+    core::mem::take(&mut *TIMESTAMPS.lock()).leak()
+}
\ No newline at end of file
@@ -1 +0,0 @@
-pub mod framebuffer;
\ No newline at end of file
@@ -0,0 +1,16 @@
+#![no_main]
+
+use formats::acpi::{self, Madt, Rsdp};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Rsdp::parse(data);
+    for xsdt in [false, true] {
+        if let Ok(entries) = acpi::root_table_entries(data, xsdt) {
+            entries.for_each(drop);
+        }
+    }
+    if let Ok(madt) = Madt::parse(data) {
+        madt.entries().for_each(drop);
+    }
+});
\ No newline at end of file
@@ -0,0 +1,10 @@
+#![no_main]
+
+use formats::edid::preferred_resolution;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok((width, height)) = preferred_resolution(data) {
+        assert!(width > 0 && width < 4096 && height > 0 && height < 4096);
+    }
+});
\ No newline at end of file
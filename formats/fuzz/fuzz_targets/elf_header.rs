@@ -0,0 +1,16 @@
+#![no_main]
+
+use formats::elf::ELFHeader;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // Parsing must never panic, and anything it accepts must be safe to slice segments out of
+    if let Ok(elf) = ELFHeader::make(data) {
+        for ph in elf.load_segments() {
+            let _ = ph.file_bytes(data);
+        }
+        if let Ok(Some(symbols)) = elf.symbol_table(data) {
+            let _ = symbols.lookup(elf.entry_function);
+        }
+    }
+});
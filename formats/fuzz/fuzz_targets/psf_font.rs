@@ -0,0 +1,12 @@
+#![no_main]
+
+use formats::psf::PsfFont;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Some(font) = PsfFont::from_bytes(data) {
+        for ascii in 0..=u8::MAX {
+            assert_eq!(font.glyph_for(ascii).len(), font.glyph_height());
+        }
+    }
+});
@@ -0,0 +1,12 @@
+#![no_main]
+
+use formats::tar::TarArchive;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // Walking must terminate without panicking, and every entry must point inside the input
+    for entry in TarArchive::new(data).entries().flatten() {
+        let start = entry.data.as_ptr() as usize - data.as_ptr() as usize;
+        assert!(start + entry.data.len() <= data.len());
+    }
+});
\ No newline at end of file
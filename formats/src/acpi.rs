@@ -0,0 +1,257 @@
+//! ACPI root pointer, system description table headers and the MADT.
+//!
+//! The parsers work on byte slices; the kernel builds those from the physical addresses
+//! the firmware hands over.
+
+pub const RSDP_V1_SIZE: usize = 20;
+pub const RSDP_V2_SIZE: usize = 36;
+pub const SDT_HEADER_SIZE: usize = 36;
+pub const MADT_SIGNATURE: &[u8; 4] = b"APIC";
+pub const MADT_HEADER_SIZE: usize = SDT_HEADER_SIZE + 8;
+pub const MADT_PCAT_COMPAT: u32 = 1 << 0; // The system also has dual 8259 PICs
+
+// Flags of interrupt source overrides and NMI entries (MPS INTI flags)
+pub const INTI_POLARITY_MASK: u16 = 0b11;
+pub const INTI_ACTIVE_HIGH: u16 = 0b01;
+pub const INTI_ACTIVE_LOW: u16 = 0b11;
+pub const INTI_TRIGGER_MASK: u16 = 0b11 << 2;
+pub const INTI_EDGE: u16 = 0b01 << 2;
+pub const INTI_LEVEL: u16 = 0b11 << 2;
+
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+
+#[derive(Debug, PartialEq)]
+pub enum AcpiError {
+    Truncated,
+    BadSignature,
+    BadChecksum,
+    BadLength(u32),
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, AcpiError> {
+    let bytes = data.get(offset..offset + 4).ok_or(AcpiError::Truncated)?;
+    Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16, AcpiError> {
+    let bytes = data.get(offset..offset + 2).ok_or(AcpiError::Truncated)?;
+    Ok(u16::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Result<u64, AcpiError> {
+    let bytes = data.get(offset..offset + 8).ok_or(AcpiError::Truncated)?;
+    Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+/// ACPI checksums make all bytes of a structure sum to zero
+fn checksum_ok(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) == 0
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rsdp {
+    pub oem_id: [u8; 6],
+    pub revision: u8, // 0 for ACPI 1.0, 2 and up for later versions
+    pub rsdt_address: u32,
+    pub xsdt_address: Option<u64>, // ACPI 2.0+
+}
+
+impl Rsdp {
+    /// Parses the root system description pointer. `data` must hold at least the
+    /// 20-byte ACPI 1.0 structure, and the full 36 bytes when the revision is 2 or later.
+    pub fn parse(data: &[u8]) -> Result<Self, AcpiError> {
+        let v1 = data.get(..RSDP_V1_SIZE).ok_or(AcpiError::Truncated)?;
+        if &v1[0..8] != RSDP_SIGNATURE {
+            return Err(AcpiError::BadSignature);
+        }
+        if !checksum_ok(v1) {
+            return Err(AcpiError::BadChecksum);
+        }
+
+        let mut oem_id = [0u8; 6];
+        oem_id.copy_from_slice(&v1[9..15]);
+        let revision = v1[15];
+        let rsdt_address = read_u32(v1, 16)?;
+
+        let mut xsdt_address = None;
+        if revision >= 2 {
+            let length = read_u32(data, 20)?;
+            if (length as usize) < RSDP_V2_SIZE {
+                return Err(AcpiError::BadLength(length));
+            }
+            let v2 = data.get(..RSDP_V2_SIZE).ok_or(AcpiError::Truncated)?;
+            if !checksum_ok(v2) {
+                return Err(AcpiError::BadChecksum);
+            }
+            xsdt_address = Some(read_u64(v2, 24)?);
+        }
+
+        Ok(Self { oem_id, revision, rsdt_address, xsdt_address })
+    }
+
+    /// The root table to walk: the XSDT if there is one, otherwise the RSDT. The flag is true for an XSDT.
+    pub fn root_table(&self) -> (u64, bool) {
+        match self.xsdt_address {
+            Some(xsdt) if xsdt != 0 => (xsdt, true),
+            _ => (self.rsdt_address as u64, false),
+        }
+    }
+}
+
+/// The header every system description table starts with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SdtHeader {
+    pub signature: [u8; 4],
+    pub length: u32, // Of the whole table, header included
+    pub revision: u8,
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
+}
+
+impl SdtHeader {
+    pub fn parse(data: &[u8]) -> Result<Self, AcpiError> {
+        let header = data.get(..SDT_HEADER_SIZE).ok_or(AcpiError::Truncated)?;
+
+        let mut signature = [0u8; 4];
+        signature.copy_from_slice(&header[0..4]);
+        let length = read_u32(header, 4)?;
+        if (length as usize) < SDT_HEADER_SIZE {
+            return Err(AcpiError::BadLength(length));
+        }
+
+        let mut oem_id = [0u8; 6];
+        oem_id.copy_from_slice(&header[10..16]);
+        let mut oem_table_id = [0u8; 8];
+        oem_table_id.copy_from_slice(&header[16..24]);
+
+        Ok(Self { signature, length, revision: header[8], oem_id, oem_table_id })
+    }
+
+    pub fn signature_str(&self) -> &str {
+        core::str::from_utf8(&self.signature).unwrap_or("????")
+    }
+}
+
+/// Validates a whole table (header and body, `length` bytes) and returns its header.
+pub fn parse_table(table: &[u8]) -> Result<SdtHeader, AcpiError> {
+    let header = SdtHeader::parse(table)?;
+    let bytes = table.get(..header.length as usize).ok_or(AcpiError::Truncated)?;
+    if !checksum_ok(bytes) {
+        return Err(AcpiError::BadChecksum);
+    }
+
+    Ok(header)
+}
+
+/// Physical addresses of the tables an XSDT (8-byte entries) or RSDT (4-byte entries) points to.
+pub fn root_table_entries(table: &[u8], xsdt: bool) -> Result<impl Iterator<Item = u64> + '_, AcpiError> {
+    let header = parse_table(table)?;
+    let body = &table[SDT_HEADER_SIZE..header.length as usize];
+    let entry_size = if xsdt { 8 } else { 4 };
+
+    Ok(body.chunks_exact(entry_size).map(move |entry| match entry.try_into() {
+        Ok(wide) => u64::from_le_bytes(wide),
+        Err(_) => u32::from_le_bytes(entry.try_into().unwrap()) as u64,
+    }))
+}
+
+/// One interrupt controller structure from the MADT
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MadtEntry {
+    LocalApic { processor_id: u8, apic_id: u8, flags: u32 },
+    IoApic { id: u8, address: u32, gsi_base: u32 },
+    InterruptSourceOverride { bus: u8, source: u8, gsi: u32, flags: u16 }, // ISA IRQ `source` arrives on `gsi`
+    NmiSource { flags: u16, gsi: u32 },
+    LocalApicNmi { processor_id: u8, flags: u16, lint: u8 }, // processor_id 0xFF means every processor
+    LocalApicAddressOverride { address: u64 },
+    LocalX2Apic { apic_id: u32, flags: u32, processor_uid: u32 },
+    LocalX2ApicNmi { processor_uid: u32, flags: u16, lint: u8 }, // processor_uid 0xFFFFFFFF means every processor
+    Unknown { kind: u8 },
+}
+
+impl MadtEntry {
+    /// Smallest length each known entry type can have
+    fn min_length(kind: u8) -> usize {
+        match kind {
+            0 => 8,
+            1 => 12,
+            2 => 10,
+            3 => 8,
+            4 => 6,
+            5 => 12,
+            9 => 16,
+            10 => 12,
+            _ => 2,
+        }
+    }
+
+    /// `entry` is a whole entry whose length was already checked against min_length
+    fn parse(entry: &[u8]) -> Result<Self, AcpiError> {
+        Ok(match entry[0] {
+            0 => MadtEntry::LocalApic { processor_id: entry[2], apic_id: entry[3], flags: read_u32(entry, 4)? },
+            1 => MadtEntry::IoApic { id: entry[2], address: read_u32(entry, 4)?, gsi_base: read_u32(entry, 8)? },
+            2 => MadtEntry::InterruptSourceOverride {
+                bus: entry[2],
+                source: entry[3],
+                gsi: read_u32(entry, 4)?,
+                flags: read_u16(entry, 8)?,
+            },
+            3 => MadtEntry::NmiSource { flags: read_u16(entry, 2)?, gsi: read_u32(entry, 4)? },
+            4 => MadtEntry::LocalApicNmi { processor_id: entry[2], flags: read_u16(entry, 3)?, lint: entry[5] },
+            5 => MadtEntry::LocalApicAddressOverride { address: read_u64(entry, 4)? },
+            9 => MadtEntry::LocalX2Apic {
+                apic_id: read_u32(entry, 4)?,
+                flags: read_u32(entry, 8)?,
+                processor_uid: read_u32(entry, 12)?,
+            },
+            10 => MadtEntry::LocalX2ApicNmi { processor_uid: read_u32(entry, 4)?, flags: read_u16(entry, 2)?, lint: entry[8] },
+            kind => MadtEntry::Unknown { kind },
+        })
+    }
+}
+
+/// The multiple APIC description table, signature "APIC"
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Madt<'a> {
+    pub local_apic_address: u32, // Physical, unless a LocalApicAddressOverride entry replaces it
+    pub flags: u32,
+    entries: &'a [u8],
+}
+
+impl<'a> Madt<'a> {
+    /// Validates the table and the length of every entry in it, so iterating the entries cannot fail
+    pub fn parse(table: &'a [u8]) -> Result<Self, AcpiError> {
+        let header = parse_table(table)?;
+        if &header.signature != MADT_SIGNATURE {
+            return Err(AcpiError::BadSignature);
+        }
+        let table = &table[..header.length as usize];
+
+        let madt = Self {
+            local_apic_address: read_u32(table, SDT_HEADER_SIZE)?,
+            flags: read_u32(table, SDT_HEADER_SIZE + 4)?,
+            entries: table.get(MADT_HEADER_SIZE..).ok_or(AcpiError::Truncated)?,
+        };
+
+        let mut rest = madt.entries;
+        while !rest.is_empty() {
+            let length = *rest.get(1).ok_or(AcpiError::Truncated)? as usize;
+            if length < MadtEntry::min_length(rest[0]) {
+                return Err(AcpiError::BadLength(length as u32));
+            }
+            rest = rest.get(length..).ok_or(AcpiError::Truncated)?;
+        }
+
+        Ok(madt)
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = MadtEntry> + 'a {
+        let mut rest = self.entries;
+        core::iter::from_fn(move || {
+            let length = *rest.get(1)? as usize;
+            let (entry, next) = rest.split_at(length);
+            rest = next;
+            MadtEntry::parse(entry).ok()
+        })
+    }
+}
\ No newline at end of file
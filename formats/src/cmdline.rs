@@ -0,0 +1,36 @@
+/// A kernel command line: whitespace separated `flag` or `key=value` words.
+#[derive(Debug, Clone, Copy)]
+pub struct Cmdline<'a> {
+    text: &'a str,
+}
+
+impl<'a> Cmdline<'a> {
+    pub fn new(text: &'a str) -> Self {
+        Self { text }
+    }
+
+    pub fn as_str(&self) -> &'a str {
+        self.text
+    }
+
+    /// Every word as a key and an optional value
+    pub fn options(&self) -> impl Iterator<Item = (&'a str, Option<&'a str>)> {
+        self.text.split_whitespace().map(|word| match word.split_once('=') {
+            Some((key, value)) => (key, Some(value)),
+            None => (word, None),
+        })
+    }
+
+    /// True if `name` appears as a bare flag
+    pub fn flag(&self, name: &str) -> bool {
+        self.options().any(|(key, value)| key == name && value.is_none())
+    }
+
+    /// The value of the last `name=value` word, so later words override earlier ones
+    pub fn value(&self, name: &str) -> Option<&'a str> {
+        self.options()
+            .filter(|(key, _)| *key == name)
+            .filter_map(|(_, value)| value)
+            .last()
+    }
+}
\ No newline at end of file
@@ -0,0 +1,273 @@
+//! The bootloader configuration file, `\boot\sorix.cfg`.
+//!
+//! One `key = value` pair per line. Blank lines and lines starting with `#` are ignored.
+//! The kernel volume is the one with the partition GUID given by `volume_guid`, if set. Otherwise it is
+//! the first volume, starting with the one the bootloader came from, that has the label given by `volume`
+//! or holds the kernel of an entry.
+//! A `[Title]` line starts a boot entry; entries start out with the `kernel`, `kernel_sha256`, `initrd`
+//! and `cmdline` given above the first entry. Without any entries, those keys form a single entry.
+//!
+//! ```text
+//! volume = OS
+//! volume_guid = 0FC63DAF-8483-4772-8E79-3D69D8477DE4
+//! # Or largest, native or current
+//! resolution = 1280x720
+//! log_level = info
+//! timeout = 3
+//! stack_size = 512K
+//! default = Release
+//! cmdline = noahci
+//! initrd = \boot\initrd.tar
+//!
+//! [Release]
+//! kernel = \boot\kernel
+//! kernel_sha256 = 9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08
+//!
+//! [Debug]
+//! kernel = \boot\kernel-debug
+//! cmdline = noahci nopci
+//! ```
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use crate::sha256::{self, DIGEST_SIZE};
+
+const DEFAULT_TITLE: &str = "Sorix";
+const DEFAULT_STACK_SIZE: usize = 256 * 1024;
+const STACK_PAGE_SIZE: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+/// The graphics mode the bootloader switches to before handing the framebuffer to the kernel
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VideoMode {
+    Current, // Keep the firmware's mode
+    Resolution(usize, usize),
+    Largest,
+    Native, // The display's preferred resolution from its EDID
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ConfigErrorKind {
+    MissingEquals,
+    UnknownKey,
+    EmptyValue,
+    BadNumber,
+    BadResolution,
+    BadLogLevel,
+    BadSection,
+    GlobalKeyInEntry,
+    UnknownDefault,
+    BadDigest,
+    BadGuid,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ConfigError {
+    pub line: usize, // 1-based
+    pub kind: ConfigErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BootEntry {
+    pub title: String,
+    pub kernel_path: String,
+    pub kernel_sha256: Option<[u8; DIGEST_SIZE]>, // Checked before the kernel is loaded
+    pub initrd_path: Option<String>, // ustar archive handed to the kernel as is
+    pub cmdline: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct BootConfig {
+    pub volume_label: String,
+    pub volume_guid: Option<[u8; 16]>, // GPT partition GUID, in the mixed-endian byte order it has on disk
+    pub video_mode: VideoMode,
+    pub log_level: LogLevel,
+    pub timeout: u32, // Seconds the boot menu waits before booting the default entry. 0 skips the menu.
+    pub stack_size: usize, // Bytes of kernel stack, a whole number of pages
+    pub default_entry: usize, // Index into entries
+    pub entries: Vec<BootEntry>, // Never empty
+}
+
+impl Default for BootEntry {
+    fn default() -> Self {
+        Self {
+            title: DEFAULT_TITLE.to_string(),
+            kernel_path: "kernel".to_string(),
+            kernel_sha256: None,
+            initrd_path: None,
+            cmdline: String::new(),
+        }
+    }
+}
+
+impl Default for BootConfig {
+    fn default() -> Self {
+        Self {
+            volume_label: "OS".to_string(),
+            volume_guid: None,
+            video_mode: VideoMode::Current,
+            log_level: LogLevel::Info,
+            timeout: 0,
+            stack_size: DEFAULT_STACK_SIZE,
+            default_entry: 0,
+            entries: alloc::vec![BootEntry::default()],
+        }
+    }
+}
+
+impl LogLevel {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "off" => Some(LogLevel::Off),
+            "error" => Some(LogLevel::Error),
+            "warn" => Some(LogLevel::Warn),
+            "info" => Some(LogLevel::Info),
+            "debug" => Some(LogLevel::Debug),
+            "trace" => Some(LogLevel::Trace),
+            _ => None,
+        }
+    }
+}
+
+fn parse_video_mode(value: &str) -> Option<VideoMode> {
+    match value {
+        "current" => return Some(VideoMode::Current),
+        "largest" => return Some(VideoMode::Largest),
+        "native" => return Some(VideoMode::Native),
+        _ => {},
+    }
+
+    let (width, height) = value.split_once('x')?;
+    let width = width.trim().parse().ok()?;
+    let height = height.trim().parse().ok()?;
+    if width == 0 || height == 0 {
+        return None;
+    }
+
+    Some(VideoMode::Resolution(width, height))
+}
+
+/// Parses the usual text form of a GUID. The first three groups are stored little-endian, the rest as written.
+fn parse_guid(value: &str) -> Option<[u8; 16]> {
+    let groups: Vec<&str> = value.split('-').collect();
+    let lengths = [8, 4, 4, 4, 12];
+    if groups.len() != lengths.len() {
+        return None;
+    }
+
+    let mut bytes = [0u8; 16];
+    let mut offset = 0;
+    for (index, (group, length)) in groups.iter().zip(lengths).enumerate() {
+        if group.len() != length || !group.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+
+        let start = offset;
+        for pair in group.as_bytes().chunks(2) {
+            bytes[offset] = u8::from_str_radix(core::str::from_utf8(pair).ok()?, 16).ok()?;
+            offset += 1;
+        }
+        if index < 3 {
+            bytes[start..offset].reverse();
+        }
+    }
+
+    Some(bytes)
+}
+
+/// A byte count with an optional K or M suffix, rounded up to whole pages
+fn parse_stack_size(value: &str) -> Option<usize> {
+    let (digits, unit) = match value.as_bytes().last()? {
+        b'K' | b'k' => (&value[..value.len() - 1], 1024),
+        b'M' | b'm' => (&value[..value.len() - 1], 1024 * 1024),
+        _ => (value, 1),
+    };
+    let size = digits.trim().parse::<usize>().ok()?.checked_mul(unit)?;
+    if size == 0 {
+        return None;
+    }
+
+    size.checked_next_multiple_of(STACK_PAGE_SIZE)
+}
+
+impl BootConfig {
+    /// Parses a configuration file. Keys that are not present keep their defaults.
+    pub fn parse(text: &str) -> Result<Self, ConfigError> {
+        let mut config = Self::default();
+        // Settings above the first [Title] line, inherited by every entry
+        let mut global = BootEntry::default();
+        let mut entries: Vec<BootEntry> = Vec::new();
+        let mut default_title = None;
+
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let error = |kind| ConfigError { line: index + 1, kind };
+
+            if let Some(section) = line.strip_prefix('[') {
+                let title = section.strip_suffix(']').map(str::trim).unwrap_or("");
+                if title.is_empty() {
+                    return Err(error(ConfigErrorKind::BadSection));
+                }
+                entries.push(BootEntry { title: title.to_string(), ..global.clone() });
+                continue;
+            }
+
+            let (key, value) = line.split_once('=').ok_or(error(ConfigErrorKind::MissingEquals))?;
+            let (key, value) = (key.trim(), value.trim());
+
+            // The command line is the only key that may be left empty
+            if value.is_empty() && key != "cmdline" {
+                return Err(error(ConfigErrorKind::EmptyValue));
+            }
+
+            let entry = entries.last_mut().unwrap_or(&mut global);
+            match key {
+                "kernel" => entry.kernel_path = value.to_string(),
+                "kernel_sha256" => {
+                    entry.kernel_sha256 = Some(sha256::parse_hex_digest(value).ok_or(error(ConfigErrorKind::BadDigest))?);
+                },
+                "initrd" => entry.initrd_path = Some(value.to_string()),
+                "cmdline" => entry.cmdline = value.to_string(),
+                "volume" | "volume_guid" | "resolution" | "log_level" | "timeout" | "stack_size" | "default" if !entries.is_empty() => {
+                    return Err(error(ConfigErrorKind::GlobalKeyInEntry));
+                },
+                "volume" => config.volume_label = value.to_string(),
+                "volume_guid" => config.volume_guid = Some(parse_guid(value).ok_or(error(ConfigErrorKind::BadGuid))?),
+                "resolution" => {
+                    config.video_mode = parse_video_mode(value).ok_or(error(ConfigErrorKind::BadResolution))?;
+                },
+                "log_level" => config.log_level = LogLevel::parse(value).ok_or(error(ConfigErrorKind::BadLogLevel))?,
+                "timeout" => config.timeout = value.parse().map_err(|_| error(ConfigErrorKind::BadNumber))?,
+                "stack_size" => config.stack_size = parse_stack_size(value).ok_or(error(ConfigErrorKind::BadNumber))?,
+                "default" => default_title = Some((index + 1, value.to_string())),
+                _ => return Err(error(ConfigErrorKind::UnknownKey)),
+            }
+        }
+
+        if entries.is_empty() {
+            entries.push(global);
+        }
+
+        if let Some((line, title)) = default_title {
+            config.default_entry = entries.iter()
+                .position(|entry| entry.title == title)
+                .ok_or(ConfigError { line, kind: ConfigErrorKind::UnknownDefault })?;
+        }
+        config.entries = entries;
+
+        Ok(config)
+    }
+}
\ No newline at end of file
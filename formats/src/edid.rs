@@ -0,0 +1,42 @@
+//! EDID base blocks, as reported by the firmware for the active display.
+//!
+//! Only the preferred timing is read, to find the display's native resolution.
+
+pub const EDID_BLOCK_SIZE: usize = 128;
+
+const EDID_HEADER: [u8; 8] = [0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00];
+const FIRST_DESCRIPTOR: usize = 54;
+
+#[derive(Debug, PartialEq)]
+pub enum EdidError {
+    Truncated,
+    BadHeader,
+    BadChecksum,
+    NoPreferredTiming,
+}
+
+/// Returns the active width and height of the preferred timing, which is the display's native resolution.
+pub fn preferred_resolution(edid: &[u8]) -> Result<(usize, usize), EdidError> {
+    let block = edid.get(..EDID_BLOCK_SIZE).ok_or(EdidError::Truncated)?;
+    if block[..8] != EDID_HEADER {
+        return Err(EdidError::BadHeader);
+    }
+    if block.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0 {
+        return Err(EdidError::BadChecksum);
+    }
+
+    // The first 18-byte descriptor is the preferred timing, unless its pixel clock is 0
+    let timing = &block[FIRST_DESCRIPTOR..FIRST_DESCRIPTOR + 18];
+    if timing[0] == 0 && timing[1] == 0 {
+        return Err(EdidError::NoPreferredTiming);
+    }
+
+    // The upper nibbles of bytes 4 and 7 hold the high bits of the active sizes
+    let width = timing[2] as usize | ((timing[4] as usize & 0xf0) << 4);
+    let height = timing[5] as usize | ((timing[7] as usize & 0xf0) << 4);
+    if width == 0 || height == 0 {
+        return Err(EdidError::NoPreferredTiming);
+    }
+
+    Ok((width, height))
+}
\ No newline at end of file
@@ -0,0 +1,638 @@
+use alloc::vec::Vec;
+use num_enum::{FromPrimitive, IntoPrimitive, TryFromPrimitive};
+
+pub const PAGE_SIZE: usize = 4096;
+
+const ELF_MAGIC: &[u8; 4] = b"\x7FELF";
+const ELF64_HEADER_SIZE: usize = 64;
+const ELF64_PROGRAM_HEADER_SIZE: usize = 56;
+const ELF64_DYN_SIZE: usize = 16;
+const ELF64_RELA_SIZE: usize = 24;
+const ELF64_SECTION_HEADER_SIZE: usize = 64;
+pub const ELF64_SYM_SIZE: usize = 24;
+
+// Dynamic section tags
+const DT_NULL: usize = 0;
+const DT_RELA: usize = 7;
+const DT_RELASZ: usize = 8;
+const DT_RELAENT: usize = 9;
+const DT_REL: usize = 17;
+
+// Section types
+pub const SHT_SYMTAB: u32 = 2;
+pub const SHT_STRTAB: u32 = 3;
+
+// Symbol types and special section indices
+pub const STT_NOTYPE: u8 = 0;
+pub const STT_FUNC: u8 = 2;
+const SHN_UNDEF: u16 = 0;
+const SHN_ABS: u16 = 0xfff1;
+
+pub const PF_X: u32 = 0x1;
+pub const PF_W: u32 = 0x2;
+
+pub const R_X86_64_NONE: u32 = 0;
+pub const R_X86_64_RELATIVE: u32 = 8;
+
+#[derive(Debug, TryFromPrimitive, IntoPrimitive)]
+#[repr(u8)]
+pub enum OSABI {
+    SystemV = 0x0,
+    HPUX = 0x01,
+    NetBSD = 0x02,
+    Linux = 0x03,
+    GNUHurd = 0x04,
+    Solaris = 0x06,
+    AIX = 0x07,
+    IRIX = 0x08,
+    FreeBSD = 0x09,
+    Tru64 = 0x0A,
+    NovellModesto = 0x0B,
+    OpenBSD = 0x0C,
+    OpenVMS = 0x0D,
+    NonStopKernel = 0x0E,
+    AROS = 0x0F,
+    FenixOS = 0x10,
+    NuxiCloudABI = 0x11,
+    OpenVOS = 0x12,
+}
+
+#[derive(Debug, PartialEq, TryFromPrimitive, IntoPrimitive)]
+#[repr(u8)]
+pub enum Endianness {
+    Little = 0x1,
+    Big = 0x2,
+}
+
+#[derive(Debug, PartialEq, TryFromPrimitive, IntoPrimitive)]
+#[repr(u8)]
+pub enum ELFClass {
+    X32 = 0x1,
+    X64 = 0x2,
+}
+
+#[derive(Debug, PartialEq, TryFromPrimitive, IntoPrimitive)]
+#[repr(u16)]
+pub enum ObjectFileType {
+    Unknown = 0x00,
+    Relocatable = 0x01,
+    Executable = 0x02,
+    SharedObject = 0x03,
+    CoreFile = 0x04,
+}
+
+#[derive(Debug, PartialEq, TryFromPrimitive, IntoPrimitive)]
+#[repr(u16)]
+pub enum ISA {
+    Unknown = 0x00,
+    X86 = 0x03,
+    ARM = 0x28,
+    AMD64 = 0x3E,
+}
+
+#[derive(Debug, PartialEq, FromPrimitive, IntoPrimitive)]
+#[repr(u32)]
+pub enum ProgramHeaderType {
+    Null = 0x00000000,
+    Load = 0x00000001,
+    Dynamic = 0x00000002,
+    Interp = 0x00000003,
+    Note = 0x00000004,
+    Reserved = 0x00000005,
+    Header = 0x00000006,
+    TLS = 0x00000007,
+    #[num_enum(catch_all)]
+    Other(u32), // OS and processor specific types such as PT_GNU_STACK
+}
+
+/// Everything that can be wrong with a kernel binary
+#[derive(Debug, PartialEq)]
+pub enum ElfError {
+    Truncated,
+    BadMagic,
+    UnsupportedClass(u8),
+    UnsupportedEndianness(u8),
+    UnsupportedOsAbi(u8),
+    UnsupportedIsa(u16),
+    UnsupportedType(u16),
+    BadProgramHeaderSize(u16),
+    ProgramHeaderTableOutOfBounds,
+    SegmentOutOfBounds(usize),
+    SegmentFileSizeTooLarge(usize),
+    SegmentAddressOverflow(usize),
+    OverlappingSegments(usize, usize),
+    NoLoadableSegments,
+    EntryOutsideSegments(usize),
+    DynamicOutOfBounds,
+    UnsupportedRelTable,
+    BadRelocationEntrySize(usize),
+    RelocationTableOutOfBounds,
+    RelocationOutsideSegments(usize),
+    BadSectionHeaderSize(u16),
+    SectionHeaderTableOutOfBounds,
+    SectionOutOfBounds(usize),
+    BadSymbolEntrySize(usize),
+    BadStringTableLink(usize),
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16, ElfError> {
+    let bytes = data.get(offset..offset + 2).ok_or(ElfError::Truncated)?;
+    Ok(u16::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, ElfError> {
+    let bytes = data.get(offset..offset + 4).ok_or(ElfError::Truncated)?;
+    Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Result<usize, ElfError> {
+    let bytes = data.get(offset..offset + 8).ok_or(ElfError::Truncated)?;
+    Ok(u64::from_le_bytes(bytes.try_into().unwrap()) as usize)
+}
+
+#[allow(dead_code)]
+pub struct ELFHeader {
+    pub elf_identity: ELFIdentity,
+    pub program_headers: Vec<ProgramHeader>,
+    pub relocations: Vec<Relocation>, // Only position-independent kernels carry any
+    pub e_type: ObjectFileType,
+    pub isa: ISA,
+    pub version: u32,
+    pub entry_function: usize,
+    pub program_header_offset: usize,
+    pub flags: u32,
+    pub header_size: u16, // Contains the size in bytes of the ELF header (64 bytes for 64-bit and 52 for 32-bit)
+    pub phentry_size: u16,
+    pub phentry_amount: u16,
+    pub section_header_offset: usize,
+    pub shentry_size: u16,
+    pub shentry_amount: u16, // 0 when the file has no section headers
+}
+
+impl ELFHeader {
+    /// Parses and validates a 64-bit little-endian AMD64 executable.
+    /// Every offset is bounds checked, so a truncated or corrupt file is an error rather than a panic.
+    pub fn make(elf_data: &[u8]) -> Result<Self, ElfError> {
+        if elf_data.len() < ELF64_HEADER_SIZE {
+            return Err(ElfError::Truncated);
+        }
+
+        let elf_identity = ELFIdentity::make(elf_data)?;
+        let (e_type, isa, version) = Self::make_first_three(elf_data)?;
+
+        let entry_function = read_u64(elf_data, 24)?;
+        let program_header_offset = read_u64(elf_data, 32)?;
+        let flags = read_u32(elf_data, 48)?;
+        let header_size = read_u16(elf_data, 52)?;
+        let phentry_size = read_u16(elf_data, 54)?;
+        let phentry_amount = read_u16(elf_data, 56)?;
+        let section_header_offset = read_u64(elf_data, 40)?;
+        let shentry_size = read_u16(elf_data, 58)?;
+        let shentry_amount = read_u16(elf_data, 60)?;
+
+        if (phentry_size as usize) < ELF64_PROGRAM_HEADER_SIZE {
+            return Err(ElfError::BadProgramHeaderSize(phentry_size));
+        }
+
+        let table_end = (phentry_size as usize)
+            .checked_mul(phentry_amount as usize)
+            .and_then(|size| size.checked_add(program_header_offset))
+            .ok_or(ElfError::ProgramHeaderTableOutOfBounds)?;
+        if table_end > elf_data.len() {
+            return Err(ElfError::ProgramHeaderTableOutOfBounds);
+        }
+
+        let mut program_headers = Vec::with_capacity(phentry_amount as usize);
+        for index in 0..phentry_amount as usize {
+            program_headers.push(ProgramHeader::new(elf_data, program_header_offset, phentry_size, index)?);
+        }
+
+        let mut header = Self {
+            elf_identity,
+            program_headers,
+            relocations: Vec::new(),
+            e_type,
+            isa,
+            version,
+            entry_function,
+            program_header_offset,
+            flags,
+            header_size,
+            phentry_size,
+            phentry_amount,
+            section_header_offset,
+            shentry_size,
+            shentry_amount
+        };
+        header.validate_segments()?;
+        header.relocations = header.parse_relocations(elf_data)?;
+
+        Ok(header)
+    }
+
+    fn make_first_three(elf_data: &[u8]) -> Result<(ObjectFileType, ISA, u32), ElfError> {
+        let etype_integer = read_u16(elf_data, 16)?;
+        let e_type = match ObjectFileType::try_from_primitive(etype_integer) {
+            Ok(ObjectFileType::Executable) => ObjectFileType::Executable,
+            Ok(ObjectFileType::SharedObject) => ObjectFileType::SharedObject,
+            _ => return Err(ElfError::UnsupportedType(etype_integer)),
+        };
+
+        let emachine_integer = read_u16(elf_data, 18)?;
+        let isa = match ISA::try_from_primitive(emachine_integer) {
+            Ok(ISA::AMD64) => ISA::AMD64,
+            _ => return Err(ElfError::UnsupportedIsa(emachine_integer)),
+        };
+
+        let version = read_u32(elf_data, 20)?;
+
+        Ok((e_type, isa, version))
+    }
+
+    pub fn load_segments(&self) -> impl Iterator<Item = &ProgramHeader> {
+        self.program_headers.iter().filter(|ph| ph.p_type == ProgramHeaderType::Load)
+    }
+
+    /// A PIE (ET_DYN) kernel can be loaded at any base as long as its relocations are applied.
+    pub fn is_position_independent(&self) -> bool {
+        self.e_type == ObjectFileType::SharedObject
+    }
+
+    /// Parses the section header table. Loading never needs it, so `make` leaves it alone
+    /// and a broken table only matters to callers that ask for sections.
+    pub fn section_headers(&self, elf_data: &[u8]) -> Result<Vec<SectionHeader>, ElfError> {
+        if self.shentry_amount == 0 {
+            return Ok(Vec::new());
+        }
+        if (self.shentry_size as usize) < ELF64_SECTION_HEADER_SIZE {
+            return Err(ElfError::BadSectionHeaderSize(self.shentry_size));
+        }
+
+        let table_end = (self.shentry_size as usize)
+            .checked_mul(self.shentry_amount as usize)
+            .and_then(|size| size.checked_add(self.section_header_offset))
+            .ok_or(ElfError::SectionHeaderTableOutOfBounds)?;
+        if table_end > elf_data.len() {
+            return Err(ElfError::SectionHeaderTableOutOfBounds);
+        }
+
+        (0..self.shentry_amount as usize)
+            .map(|index| SectionHeader::new(elf_data, self.section_header_offset + index * self.shentry_size as usize))
+            .collect()
+    }
+
+    /// Finds `.symtab` and the string table it links to. None if the file has been stripped.
+    pub fn symbol_table<'a>(&self, elf_data: &'a [u8]) -> Result<Option<SymbolTable<'a>>, ElfError> {
+        let sections = self.section_headers(elf_data)?;
+        let (index, symtab) = match sections.iter().enumerate().find(|(_, sh)| sh.sh_type == SHT_SYMTAB) {
+            Some(s) => s,
+            None => return Ok(None),
+        };
+        if symtab.entry_size != ELF64_SYM_SIZE {
+            return Err(ElfError::BadSymbolEntrySize(symtab.entry_size));
+        }
+
+        let link = symtab.link as usize;
+        let strtab = sections.get(link)
+            .filter(|sh| sh.sh_type == SHT_STRTAB)
+            .ok_or(ElfError::BadStringTableLink(link))?;
+
+        let symbols = symtab.file_bytes(elf_data).ok_or(ElfError::SectionOutOfBounds(index))?;
+        let strings = strtab.file_bytes(elf_data).ok_or(ElfError::SectionOutOfBounds(link))?;
+        Ok(Some(SymbolTable::new(symbols, strings)))
+    }
+
+    /// Maps a virtual address to its offset in the file, if some PT_LOAD segment holds it on disk.
+    fn file_offset(&self, vaddr: usize) -> Option<usize> {
+        self.load_segments()
+            .find(|ph| vaddr >= ph.non_aligned_vaddr && vaddr - ph.non_aligned_vaddr < ph.file_size)
+            .map(|ph| ph.offset + (vaddr - ph.non_aligned_vaddr))
+    }
+
+    /// Walks PT_DYNAMIC and reads the RELA table it points to. Every relocation target is checked
+    /// to lie inside a PT_LOAD segment, so applying them can never write outside the loaded image.
+    fn parse_relocations(&self, elf_data: &[u8]) -> Result<Vec<Relocation>, ElfError> {
+        let dynamic = match self.program_headers.iter().find(|ph| ph.p_type == ProgramHeaderType::Dynamic) {
+            Some(ph) => ph,
+            None => return Ok(Vec::new()),
+        };
+
+        let dynamic_bytes = dynamic.offset.checked_add(dynamic.file_size)
+            .and_then(|end| elf_data.get(dynamic.offset..end))
+            .ok_or(ElfError::DynamicOutOfBounds)?;
+
+        let mut rela = None;
+        let mut rela_size = 0;
+        let mut rela_entry_size = ELF64_RELA_SIZE;
+        for entry in dynamic_bytes.chunks_exact(ELF64_DYN_SIZE) {
+            let tag = read_u64(entry, 0)?;
+            let value = read_u64(entry, 8)?;
+            match tag {
+                DT_NULL => break,
+                DT_RELA => rela = Some(value),
+                DT_RELASZ => rela_size = value,
+                DT_RELAENT => rela_entry_size = value,
+                DT_REL => return Err(ElfError::UnsupportedRelTable),
+                _ => {},
+            }
+        }
+
+        let rela = match rela {
+            Some(r) => r,
+            None => return Ok(Vec::new()),
+        };
+        if rela_entry_size < ELF64_RELA_SIZE {
+            return Err(ElfError::BadRelocationEntrySize(rela_entry_size));
+        }
+
+        let table_start = self.file_offset(rela).ok_or(ElfError::RelocationTableOutOfBounds)?;
+        let table = table_start.checked_add(rela_size)
+            .and_then(|end| elf_data.get(table_start..end))
+            .ok_or(ElfError::RelocationTableOutOfBounds)?;
+
+        let mut relocations = Vec::with_capacity(rela_size / rela_entry_size);
+        for entry in table.chunks_exact(rela_entry_size) {
+            let relocation = Relocation::new(entry)?;
+            let in_image = self.load_segments().any(|ph| relocation.offset >= ph.non_aligned_vaddr
+                && relocation.offset.checked_add(8).is_some_and(|end| end <= ph.non_aligned_vaddr + ph.memory_size));
+            if !in_image {
+                return Err(ElfError::RelocationOutsideSegments(relocation.offset));
+            }
+            relocations.push(relocation);
+        }
+
+        Ok(relocations)
+    }
+
+    fn validate_segments(&self) -> Result<(), ElfError> {
+        let loads: Vec<(usize, &ProgramHeader)> = self.program_headers.iter()
+            .enumerate()
+            .filter(|(_, ph)| ph.p_type == ProgramHeaderType::Load)
+            .collect();
+
+        if loads.is_empty() {
+            return Err(ElfError::NoLoadableSegments);
+        }
+
+        for (i, (index, ph)) in loads.iter().enumerate() {
+            for (other_index, other) in &loads[i + 1..] {
+                let overlaps = ph.non_aligned_vaddr < other.non_aligned_vaddr + other.memory_size
+                    && other.non_aligned_vaddr < ph.non_aligned_vaddr + ph.memory_size;
+                if overlaps {
+                    return Err(ElfError::OverlappingSegments(*index, *other_index));
+                }
+            }
+        }
+
+        let entry = self.entry_function;
+        let entry_is_code = loads.iter().any(|(_, ph)| ph.flags & PF_X != 0
+            && entry >= ph.non_aligned_vaddr
+            && entry < ph.non_aligned_vaddr + ph.memory_size);
+        if !entry_is_code {
+            return Err(ElfError::EntryOutsideSegments(entry));
+        }
+
+        Ok(())
+    }
+}
+
+#[allow(dead_code)]
+pub struct ELFIdentity {
+    pub magic: [u8; 4],
+    pub class: ELFClass,
+    pub endianness: Endianness,
+    pub ei_version: u8,
+    pub os_abi: OSABI,
+    pub abi_version: u8, // Mostly unused. Can ignore but keep as a field.
+}
+
+impl ELFIdentity {
+    pub fn make(elf_data: &[u8]) -> Result<Self, ElfError> {
+        let ident = elf_data.get(0..16).ok_or(ElfError::Truncated)?;
+        if &ident[0..4] != ELF_MAGIC {
+            return Err(ElfError::BadMagic);
+        }
+
+        let mut magic = [0u8; 4];
+        magic.copy_from_slice(&ident[0..4]);
+
+        let class = match ELFClass::try_from_primitive(ident[4]) {
+            Ok(ELFClass::X64) => ELFClass::X64,
+            _ => return Err(ElfError::UnsupportedClass(ident[4])),
+        };
+        let endianness = match Endianness::try_from_primitive(ident[5]) {
+            Ok(Endianness::Little) => Endianness::Little,
+            _ => return Err(ElfError::UnsupportedEndianness(ident[5])),
+        };
+
+        let os_abi = OSABI::try_from_primitive(ident[7]).map_err(|_| ElfError::UnsupportedOsAbi(ident[7]))?;
+
+        let ei_version = ident[6];
+        let abi_version = ident[8];
+
+        Ok(Self {
+            magic,
+            class,
+            endianness,
+            os_abi,
+            ei_version,
+            abi_version
+        })
+    }
+}
+
+#[allow(dead_code)]
+pub struct ProgramHeader {
+    pub p_type: ProgramHeaderType,
+    pub flags: u32,
+    pub offset: usize,
+    pub aligned_vaddr: usize,
+    pub non_aligned_vaddr: usize,
+    pub physical_addr: usize,
+    pub file_size: usize,
+    pub memory_size: usize,
+    pub vaddr_end: usize,
+    pub page_count: usize,
+}
+
+impl ProgramHeader {
+    pub fn new(elf_data: &[u8], ph_offset: usize, phentry_size: u16, index: usize) -> Result<Self, ElfError> {
+        let ph_offset = ph_offset + index * phentry_size as usize;
+        let p_type = ProgramHeaderType::from_primitive(read_u32(elf_data, ph_offset)?);
+        let flags = read_u32(elf_data, ph_offset + 4)?;
+        let offset = read_u64(elf_data, ph_offset + 8)?;
+        let non_aligned_vaddr = read_u64(elf_data, ph_offset + 16)?;
+        let physical_addr = read_u64(elf_data, ph_offset + 24)?;
+        let file_size = read_u64(elf_data, ph_offset + 32)?;
+        let memory_size = read_u64(elf_data, ph_offset + 40)?;
+
+        let mut aligned_vaddr = 0;
+        let mut vaddr_end = 0;
+        let mut page_count = 0;
+        if p_type == ProgramHeaderType::Load {
+            if file_size > memory_size {
+                return Err(ElfError::SegmentFileSizeTooLarge(index));
+            }
+
+            match offset.checked_add(file_size) {
+                Some(end) if end <= elf_data.len() => {},
+                _ => return Err(ElfError::SegmentOutOfBounds(index)),
+            }
+
+            let end = non_aligned_vaddr.checked_add(memory_size)
+                .and_then(|end| end.checked_add(PAGE_SIZE - 1))
+                .ok_or(ElfError::SegmentAddressOverflow(index))?;
+
+            // Alignment
+            aligned_vaddr = non_aligned_vaddr & !(PAGE_SIZE - 1);
+            vaddr_end = end & !(PAGE_SIZE - 1);
+            page_count = (vaddr_end - aligned_vaddr) / PAGE_SIZE;
+        }
+
+        Ok(Self {
+            p_type,
+            flags,
+            offset,
+            aligned_vaddr,
+            non_aligned_vaddr,
+            physical_addr,
+            file_size,
+            memory_size,
+            vaddr_end,
+            page_count
+        })
+    }
+
+    /// The segment's file contents, or None if they do not fit in the file
+    pub fn file_bytes<'a>(&self, elf_data: &'a [u8]) -> Option<&'a [u8]> {
+        let end = self.offset.checked_add(self.file_size)?;
+        elf_data.get(self.offset..end)
+    }
+}
+
+/// One Elf64_Rela entry
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Relocation {
+    pub offset: usize, // Virtual address of the word to patch, relative to the link-time base
+    pub r_type: u32,
+    pub symbol: u32,
+    pub addend: i64,
+}
+
+impl Relocation {
+    fn new(entry: &[u8]) -> Result<Self, ElfError> {
+        let offset = read_u64(entry, 0)?;
+        let info = read_u64(entry, 8)? as u64;
+        let addend = read_u64(entry, 16)? as i64;
+
+        Ok(Self {
+            offset,
+            r_type: info as u32,
+            symbol: (info >> 32) as u32,
+            addend
+        })
+    }
+}
+
+/// One Elf64_Shdr. Only the fields needed to find sections in the file are kept.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SectionHeader {
+    pub name: u32, // Offset into the section header string table
+    pub sh_type: u32,
+    pub flags: usize,
+    pub addr: usize,
+    pub offset: usize,
+    pub size: usize,
+    pub link: u32,
+    pub info: u32,
+    pub entry_size: usize,
+}
+
+impl SectionHeader {
+    fn new(elf_data: &[u8], offset: usize) -> Result<Self, ElfError> {
+        Ok(Self {
+            name: read_u32(elf_data, offset)?,
+            sh_type: read_u32(elf_data, offset + 4)?,
+            flags: read_u64(elf_data, offset + 8)?,
+            addr: read_u64(elf_data, offset + 16)?,
+            offset: read_u64(elf_data, offset + 24)?,
+            size: read_u64(elf_data, offset + 32)?,
+            link: read_u32(elf_data, offset + 40)?,
+            info: read_u32(elf_data, offset + 44)?,
+            entry_size: read_u64(elf_data, offset + 56)?,
+        })
+    }
+
+    /// The section's contents, or None if they do not fit in the file
+    pub fn file_bytes<'a>(&self, elf_data: &'a [u8]) -> Option<&'a [u8]> {
+        let end = self.offset.checked_add(self.size)?;
+        elf_data.get(self.offset..end)
+    }
+}
+
+/// One Elf64_Sym
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Symbol<'a> {
+    pub name: &'a str, // Empty if the name is missing or not UTF-8
+    pub value: usize, // Link-time address for symbols in an executable
+    pub size: usize,
+    pub kind: u8, // STT_*
+    pub section: u16,
+}
+
+/// `.symtab` and its `.strtab`. Both are plain byte slices, so the kernel can rebuild this
+/// from the copies the bootloader hands over.
+#[derive(Debug, Clone, Copy)]
+pub struct SymbolTable<'a> {
+    symbols: &'a [u8],
+    strings: &'a [u8],
+}
+
+impl<'a> SymbolTable<'a> {
+    pub fn new(symbols: &'a [u8], strings: &'a [u8]) -> Self {
+        Self { symbols, strings }
+    }
+
+    pub fn symbols(&self) -> &'a [u8] {
+        self.symbols
+    }
+
+    pub fn strings(&self) -> &'a [u8] {
+        self.strings
+    }
+
+    fn name(&self, offset: usize) -> &'a str {
+        let bytes = self.strings.get(offset..).unwrap_or(&[]);
+        let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+        core::str::from_utf8(&bytes[..end]).unwrap_or("")
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Symbol<'a>> + '_ {
+        self.symbols.chunks_exact(ELF64_SYM_SIZE).map(|entry| {
+            let name = u32::from_le_bytes(entry[0..4].try_into().unwrap());
+            Symbol {
+                name: self.name(name as usize),
+                kind: entry[4] & 0xf,
+                section: u16::from_le_bytes(entry[6..8].try_into().unwrap()),
+                value: u64::from_le_bytes(entry[8..16].try_into().unwrap()) as usize,
+                size: u64::from_le_bytes(entry[16..24].try_into().unwrap()) as usize,
+            }
+        })
+    }
+
+    pub fn find(&self, name: &str) -> Option<Symbol<'a>> {
+        self.iter().find(|symbol| symbol.name == name)
+    }
+
+    /// Finds the function or label an address belongs to, with the offset into it.
+    /// Symbols with a size must contain the address; sizeless ones (assembly labels)
+    /// cover everything up to the next symbol.
+    pub fn lookup(&self, address: usize) -> Option<(Symbol<'a>, usize)> {
+        self.iter()
+            .filter(|symbol| matches!(symbol.kind, STT_FUNC | STT_NOTYPE) && !symbol.name.is_empty())
+            .filter(|symbol| symbol.section != SHN_UNDEF && symbol.section != SHN_ABS)
+            .filter(|symbol| address >= symbol.value && (symbol.size == 0 || address - symbol.value < symbol.size))
+            .max_by_key(|symbol| (symbol.value, symbol.size))
+            .map(|symbol| (symbol, address - symbol.value))
+    }
+}
\ No newline at end of file
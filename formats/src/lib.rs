@@ -0,0 +1,16 @@
+//! Pure byte parsers shared by the bootloader and the kernel.
+//!
+//! Nothing in here touches firmware or hardware, so the crate also builds for the host
+//! and is covered by `cargo test -p formats` and the targets under `fuzz/`.
+#![no_std]
+
+extern crate alloc;
+
+pub mod acpi;
+pub mod cmdline;
+pub mod config;
+pub mod edid;
+pub mod elf;
+pub mod psf;
+pub mod sha256;
+pub mod tar;
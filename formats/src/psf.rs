@@ -0,0 +1,50 @@
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+const PSF1_MODE512: u8 = 0x01;
+const PSF1_HEADER_SIZE: usize = 4;
+
+/// A PSF1 bitmap font: 8 pixels wide, one byte per glyph row.
+#[derive(Debug)]
+pub struct PsfFont<'a> {
+    glyphs: &'a [u8],
+    glyph_height: usize,
+    glyph_num: usize,
+}
+
+impl<'a> PsfFont<'a> {
+    pub fn from_bytes(data: &'a [u8]) -> Option<Self> {
+        if data.len() < PSF1_HEADER_SIZE || data[0..2] != PSF1_MAGIC {
+            return None;
+        }
+
+        let mode = data[2];
+        let glyph_height = data[3] as usize;
+        let glyph_num = if mode & PSF1_MODE512 != 0 { 512 } else { 256 };
+        let glyphs = &data[PSF1_HEADER_SIZE..];
+
+        if glyph_height == 0 || glyphs.len() < glyph_height * glyph_num {
+            return None;
+        }
+
+        Some(Self {
+            glyphs,
+            glyph_height,
+            glyph_num
+        })
+    }
+
+    pub fn glyph_for(&self, ascii: u8) -> &[u8] {
+        let index = ascii as usize;
+        let start = index * self.glyph_height;
+        let end = start + self.glyph_height;
+
+        &self.glyphs[start..end]
+    }
+
+    pub fn glyph_height(&self) -> usize {
+        self.glyph_height
+    }
+
+    pub fn glyph_count(&self) -> usize {
+        self.glyph_num
+    }
+}
@@ -0,0 +1,155 @@
+//! Read-only access to ustar archives, the format of the initial ramdisk.
+use alloc::string::String;
+
+const BLOCK_SIZE: usize = 512;
+const USTAR_MAGIC: &[u8; 5] = b"ustar";
+
+#[derive(Debug, PartialEq)]
+pub enum TarError {
+    Truncated,
+    BadMagic,
+    BadChecksum,
+    BadNumber,
+    BadName,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EntryKind {
+    File,
+    Directory,
+    Other(u8),
+}
+
+#[derive(Debug)]
+pub struct TarEntry<'a> {
+    pub prefix: &'a str,
+    pub name: &'a str,
+    pub kind: EntryKind,
+    pub data: &'a [u8],
+}
+
+impl TarEntry<'_> {
+    pub fn path(&self) -> String {
+        let mut path = String::from(self.prefix);
+        if !path.is_empty() {
+            path.push('/');
+        }
+        path.push_str(self.name);
+        path
+    }
+}
+
+fn field_str(field: &[u8]) -> Result<&str, TarError> {
+    let end = field.iter().position(|b| *b == 0).unwrap_or(field.len());
+    core::str::from_utf8(&field[..end]).map_err(|_| TarError::BadName)
+}
+
+/// Numeric fields are NUL or space terminated octal
+fn field_octal(field: &[u8]) -> Result<usize, TarError> {
+    let digits = field_str(field)?.trim_matches(|c| c == ' ' || c == '\0');
+    if digits.is_empty() {
+        return Ok(0);
+    }
+
+    usize::from_str_radix(digits, 8).map_err(|_| TarError::BadNumber)
+}
+
+fn checksum_matches(header: &[u8]) -> Result<bool, TarError> {
+    let expected = field_octal(&header[148..156])?;
+    // The checksum field itself counts as eight spaces
+    let sum: usize = header.iter()
+        .enumerate()
+        .map(|(i, b)| if (148..156).contains(&i) { b' ' as usize } else { *b as usize })
+        .sum();
+
+    Ok(sum == expected)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TarArchive<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> TarArchive<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    pub fn entries(&self) -> TarEntries<'a> {
+        TarEntries { data: self.data, offset: 0, failed: false }
+    }
+
+    /// Finds a regular file by its full path inside the archive, ignoring a leading `./`
+    pub fn find(&self, path: &str) -> Option<&'a [u8]> {
+        let path = path.trim_start_matches("./");
+        self.entries()
+            .map_while(Result::ok)
+            .find(|entry| entry.kind == EntryKind::File && entry.path().trim_start_matches("./") == path)
+            .map(|entry| entry.data)
+    }
+}
+
+/// Walks the headers in order. Stops at the end-of-archive block, and after yielding an error.
+pub struct TarEntries<'a> {
+    data: &'a [u8],
+    offset: usize,
+    failed: bool,
+}
+
+impl<'a> TarEntries<'a> {
+    fn parse_entry(&mut self) -> Result<Option<TarEntry<'a>>, TarError> {
+        if self.offset == self.data.len() {
+            // Some tools leave out the end-of-archive blocks
+            return Ok(None);
+        }
+
+        let header = self.data.get(self.offset..self.offset + BLOCK_SIZE).ok_or(TarError::Truncated)?;
+        if header.iter().all(|b| *b == 0) {
+            return Ok(None);
+        }
+
+        if &header[257..262] != USTAR_MAGIC {
+            return Err(TarError::BadMagic);
+        }
+        if !checksum_matches(header)? {
+            return Err(TarError::BadChecksum);
+        }
+
+        let name = field_str(&header[0..100])?;
+        let prefix = field_str(&header[345..500])?;
+        let size = field_octal(&header[124..136])?;
+        let kind = match header[156] {
+            b'0' | 0 => EntryKind::File,
+            b'5' => EntryKind::Directory,
+            other => EntryKind::Other(other),
+        };
+
+        let data_start = self.offset + BLOCK_SIZE;
+        let data = data_start.checked_add(size)
+            .and_then(|end| self.data.get(data_start..end))
+            .ok_or(TarError::Truncated)?;
+
+        // File data is padded to a whole block
+        self.offset = (data_start + size).next_multiple_of(BLOCK_SIZE).min(self.data.len());
+
+        Ok(Some(TarEntry { prefix, name, kind, data }))
+    }
+}
+
+impl<'a> Iterator for TarEntries<'a> {
+    type Item = Result<TarEntry<'a>, TarError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        match self.parse_entry() {
+            Ok(entry) => entry.map(Ok),
+            Err(e) => {
+                self.failed = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
\ No newline at end of file
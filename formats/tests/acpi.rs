@@ -0,0 +1,159 @@
+use formats::acpi::{parse_table, root_table_entries, AcpiError, Madt, MadtEntry, Rsdp, SdtHeader, INTI_ACTIVE_LOW, INTI_LEVEL, MADT_PCAT_COMPAT, SDT_HEADER_SIZE};
+
+/// Sets the byte at `offset` so that `bytes` sums to zero
+fn fix_checksum(bytes: &mut [u8], offset: usize) {
+    bytes[offset] = 0;
+    let sum = bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+    bytes[offset] = 0u8.wrapping_sub(sum);
+}
+
+fn rsdp_v2(rsdt: u32, xsdt: u64) -> Vec<u8> {
+    let mut rsdp = Vec::new();
+    rsdp.extend_from_slice(b"RSD PTR ");
+    rsdp.push(0);
+    rsdp.extend_from_slice(b"SORIX ");
+    rsdp.push(2);
+    rsdp.extend_from_slice(&rsdt.to_le_bytes());
+    rsdp.extend_from_slice(&36u32.to_le_bytes());
+    rsdp.extend_from_slice(&xsdt.to_le_bytes());
+    rsdp.extend_from_slice(&[0; 4]);
+    fix_checksum(&mut rsdp[..20], 8);
+    fix_checksum(&mut rsdp, 32);
+    rsdp
+}
+
+fn table(signature: &[u8; 4], body: &[u8]) -> Vec<u8> {
+    let mut table = Vec::new();
+    table.extend_from_slice(signature);
+    table.extend_from_slice(&((SDT_HEADER_SIZE + body.len()) as u32).to_le_bytes());
+    table.push(1);
+    table.push(0);
+    table.extend_from_slice(b"SORIX ");
+    table.extend_from_slice(b"SORIXTBL");
+    table.extend_from_slice(&[0; 12]);
+    table.extend_from_slice(body);
+    fix_checksum(&mut table, 9);
+    table
+}
+
+#[test]
+fn parses_acpi2_rsdp() {
+    let rsdp = Rsdp::parse(&rsdp_v2(0x1000, 0x2000)).unwrap();
+
+    assert_eq!(&rsdp.oem_id, b"SORIX ");
+    assert_eq!(rsdp.revision, 2);
+    assert_eq!(rsdp.xsdt_address, Some(0x2000));
+    assert_eq!(rsdp.root_table(), (0x2000, true));
+}
+
+#[test]
+fn acpi1_rsdp_falls_back_to_rsdt() {
+    let mut data = rsdp_v2(0x1000, 0x2000)[..20].to_vec();
+    data[15] = 0;
+    fix_checksum(&mut data, 8);
+    let rsdp = Rsdp::parse(&data).unwrap();
+
+    assert_eq!(rsdp.xsdt_address, None);
+    assert_eq!(rsdp.root_table(), (0x1000, false));
+}
+
+#[test]
+fn rejects_bad_rsdp() {
+    let mut bad_signature = rsdp_v2(0x1000, 0x2000);
+    bad_signature[0] = b'X';
+    assert_eq!(Rsdp::parse(&bad_signature), Err(AcpiError::BadSignature));
+
+    let mut bad_checksum = rsdp_v2(0x1000, 0x2000);
+    bad_checksum[16] ^= 1;
+    assert_eq!(Rsdp::parse(&bad_checksum), Err(AcpiError::BadChecksum));
+
+    // Only the extended part is corrupt
+    let mut bad_extended = rsdp_v2(0x1000, 0x2000);
+    bad_extended[24] ^= 1;
+    assert_eq!(Rsdp::parse(&bad_extended), Err(AcpiError::BadChecksum));
+
+    assert_eq!(Rsdp::parse(&rsdp_v2(0x1000, 0x2000)[..30]), Err(AcpiError::Truncated));
+}
+
+#[test]
+fn walks_xsdt_and_rsdt() {
+    let mut xsdt_body = Vec::new();
+    xsdt_body.extend_from_slice(&0x1_0000_0000u64.to_le_bytes());
+    xsdt_body.extend_from_slice(&0x3000u64.to_le_bytes());
+    let xsdt = table(b"XSDT", &xsdt_body);
+    assert_eq!(root_table_entries(&xsdt, true).unwrap().collect::<Vec<_>>(), [0x1_0000_0000, 0x3000]);
+
+    let mut rsdt_body = Vec::new();
+    rsdt_body.extend_from_slice(&0x3000u32.to_le_bytes());
+    rsdt_body.extend_from_slice(&0x4000u32.to_le_bytes());
+    let rsdt = table(b"RSDT", &rsdt_body);
+    assert_eq!(root_table_entries(&rsdt, false).unwrap().collect::<Vec<_>>(), [0x3000, 0x4000]);
+}
+
+#[test]
+fn validates_table_headers() {
+    let apic = table(b"APIC", &[1, 2, 3, 4]);
+    let header = parse_table(&apic).unwrap();
+    assert_eq!(header.signature_str(), "APIC");
+    assert_eq!(header.length as usize, SDT_HEADER_SIZE + 4);
+    assert_eq!(&header.oem_table_id, b"SORIXTBL");
+
+    let mut corrupt = apic.clone();
+    corrupt[SDT_HEADER_SIZE] ^= 0xFF;
+    assert_eq!(parse_table(&corrupt), Err(AcpiError::BadChecksum));
+
+    assert_eq!(parse_table(&apic[..SDT_HEADER_SIZE + 2]), Err(AcpiError::Truncated));
+
+    let mut short = apic.clone();
+    short[4] = 8;
+    assert_eq!(SdtHeader::parse(&short), Err(AcpiError::BadLength(8)));
+}
+
+fn madt(entries: &[&[u8]]) -> Vec<u8> {
+    let mut body = Vec::new();
+    body.extend_from_slice(&0xFEE0_0000u32.to_le_bytes());
+    body.extend_from_slice(&MADT_PCAT_COMPAT.to_le_bytes());
+    for entry in entries {
+        body.extend_from_slice(entry);
+    }
+    table(b"APIC", &body)
+}
+
+#[test]
+fn parses_madt_entries() {
+    let table = madt(&[
+        &[0, 8, 0, 0, 1, 0, 0, 0],
+        &[1, 12, 2, 0, 0x00, 0x00, 0xC0, 0xFE, 0, 0, 0, 0],
+        &[2, 10, 0, 0, 2, 0, 0, 0, 0, 0],
+        &[2, 10, 0, 9, 9, 0, 0, 0, 0x0F, 0],
+        &[4, 6, 0xFF, 5, 0, 1],
+        &[9, 16, 0, 0, 0x00, 0x01, 0, 0, 1, 0, 0, 0, 7, 0, 0, 0],
+        &[0x7F, 4, 0, 0],
+    ]);
+    let madt = Madt::parse(&table).unwrap();
+
+    assert_eq!(madt.local_apic_address, 0xFEE0_0000);
+    assert_eq!(madt.flags, MADT_PCAT_COMPAT);
+    assert_eq!(madt.entries().collect::<Vec<_>>(), [
+        MadtEntry::LocalApic { processor_id: 0, apic_id: 0, flags: 1 },
+        MadtEntry::IoApic { id: 2, address: 0xFEC0_0000, gsi_base: 0 },
+        MadtEntry::InterruptSourceOverride { bus: 0, source: 0, gsi: 2, flags: 0 },
+        MadtEntry::InterruptSourceOverride { bus: 0, source: 9, gsi: 9, flags: INTI_LEVEL | INTI_ACTIVE_LOW },
+        MadtEntry::LocalApicNmi { processor_id: 0xFF, flags: 5, lint: 1 },
+        MadtEntry::LocalX2Apic { apic_id: 0x100, flags: 1, processor_uid: 7 },
+        MadtEntry::Unknown { kind: 0x7F },
+    ]);
+}
+
+#[test]
+fn rejects_malformed_madt() {
+    assert_eq!(Madt::parse(&table(b"FACP", &[0; 8])), Err(AcpiError::BadSignature));
+    assert_eq!(Madt::parse(&table(b"APIC", &[0; 4])), Err(AcpiError::Truncated));
+
+    // An entry that claims no length would never advance
+    assert_eq!(Madt::parse(&madt(&[&[0x7F, 0]])), Err(AcpiError::BadLength(0)));
+    // An I/O APIC entry too short for its address
+    assert_eq!(Madt::parse(&madt(&[&[1, 8, 0, 0, 0, 0, 0, 0]])), Err(AcpiError::BadLength(8)));
+    // The last entry runs past the end of the table
+    assert_eq!(Madt::parse(&madt(&[&[0, 8, 0, 0, 1, 0]])), Err(AcpiError::Truncated));
+}
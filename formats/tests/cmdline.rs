@@ -0,0 +1,14 @@
+use formats::cmdline::Cmdline;
+
+#[test]
+fn cmdline_flags_and_values() {
+    let cmdline = Cmdline::new("  noahci log=serial  quiet log=screen ");
+
+    assert!(cmdline.flag("noahci"));
+    assert!(cmdline.flag("quiet"));
+    assert!(!cmdline.flag("log"));
+    assert!(!cmdline.flag("noac"));
+    assert_eq!(cmdline.value("log"), Some("screen"));
+    assert_eq!(cmdline.value("noahci"), None);
+    assert_eq!(cmdline.options().count(), 4);
+}
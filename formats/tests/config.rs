@@ -0,0 +1,131 @@
+use formats::config::{BootConfig, BootEntry, ConfigError, ConfigErrorKind, LogLevel, VideoMode};
+
+#[test]
+fn empty_file_is_all_defaults() {
+    assert_eq!(BootConfig::parse("").unwrap(), BootConfig::default());
+    assert_eq!(BootConfig::parse("\n# comment only\n\n").unwrap(), BootConfig::default());
+}
+
+#[test]
+fn parses_every_key() {
+    let text = "
+        # Sorix boot configuration
+        volume = SORIX
+        volume_guid = 0fc63daf-8483-4772-8e79-3d69d8477de4
+        kernel = \\boot\\kernel
+        kernel_sha256 = ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad
+        resolution = 1280x720
+        log_level = debug
+        initrd = \\boot\\initrd.tar
+        timeout = 5
+        stack_size = 64K
+        cmdline = noahci log=serial
+    ";
+    let config = BootConfig::parse(text).unwrap();
+
+    assert_eq!(config.volume_label, "SORIX");
+    assert_eq!(config.volume_guid, Some([
+        0xAF, 0x3D, 0xC6, 0x0F, 0x83, 0x84, 0x72, 0x47, 0x8E, 0x79, 0x3D, 0x69, 0xD8, 0x47, 0x7D, 0xE4,
+    ]));
+    assert_eq!(config.video_mode, VideoMode::Resolution(1280, 720));
+    assert_eq!(config.log_level, LogLevel::Debug);
+    assert_eq!(config.timeout, 5);
+    assert_eq!(config.stack_size, 64 * 1024);
+    assert_eq!(config.default_entry, 0);
+    assert_eq!(config.entries, [BootEntry {
+        title: "Sorix".to_string(),
+        kernel_path: "\\boot\\kernel".to_string(),
+        kernel_sha256: Some(formats::sha256::digest(b"abc")),
+        initrd_path: Some("\\boot\\initrd.tar".to_string()),
+        cmdline: "noahci log=serial".to_string(),
+    }]);
+}
+
+#[test]
+fn accepts_crlf_and_empty_cmdline() {
+    let config = BootConfig::parse("timeout=2\r\ncmdline=\r\n").unwrap();
+    assert_eq!(config.timeout, 2);
+    assert_eq!(config.entries[0].cmdline, "");
+}
+
+#[test]
+fn reports_the_failing_line() {
+    let error = |line, kind| Err(ConfigError { line, kind });
+
+    assert_eq!(BootConfig::parse("timeout = 1\nkernel"), error(2, ConfigErrorKind::MissingEquals));
+    assert_eq!(BootConfig::parse("colour = red"), error(1, ConfigErrorKind::UnknownKey));
+    assert_eq!(BootConfig::parse("kernel ="), error(1, ConfigErrorKind::EmptyValue));
+    assert_eq!(BootConfig::parse("timeout = soon"), error(1, ConfigErrorKind::BadNumber));
+    assert_eq!(BootConfig::parse("stack_size = 0"), error(1, ConfigErrorKind::BadNumber));
+    assert_eq!(BootConfig::parse("stack_size = 8G"), error(1, ConfigErrorKind::BadNumber));
+    assert_eq!(BootConfig::parse("resolution = 1280"), error(1, ConfigErrorKind::BadResolution));
+    assert_eq!(BootConfig::parse("resolution = 0x720"), error(1, ConfigErrorKind::BadResolution));
+    assert_eq!(BootConfig::parse("resolution = biggest"), error(1, ConfigErrorKind::BadResolution));
+    assert_eq!(BootConfig::parse("\n\nlog_level = loud"), error(3, ConfigErrorKind::BadLogLevel));
+    assert_eq!(BootConfig::parse("[]"), error(1, ConfigErrorKind::BadSection));
+    assert_eq!(BootConfig::parse("[Release"), error(1, ConfigErrorKind::BadSection));
+    assert_eq!(BootConfig::parse("[Release]\ntimeout = 1"), error(2, ConfigErrorKind::GlobalKeyInEntry));
+    assert_eq!(BootConfig::parse("[Release]\nstack_size = 1M"), error(2, ConfigErrorKind::GlobalKeyInEntry));
+    assert_eq!(BootConfig::parse("[Release]\ncolour = red"), error(2, ConfigErrorKind::UnknownKey));
+    assert_eq!(BootConfig::parse("default = Debug\n[Release]"), error(1, ConfigErrorKind::UnknownDefault));
+    assert_eq!(BootConfig::parse("kernel_sha256 = abc"), error(1, ConfigErrorKind::BadDigest));
+    assert_eq!(BootConfig::parse("volume_guid = 0FC63DAF-8483-4772-8E79"), error(1, ConfigErrorKind::BadGuid));
+    assert_eq!(BootConfig::parse("volume_guid = 0FC63DAF-8483-4772-8E79-3D69D8477DE+"), error(1, ConfigErrorKind::BadGuid));
+    assert_eq!(BootConfig::parse("volume_guid = 0FC63DAF8-483-4772-8E79-3D69D8477DE4"), error(1, ConfigErrorKind::BadGuid));
+}
+
+#[test]
+fn entries_inherit_global_kernel_and_cmdline() {
+    let text = "
+        default = Debug
+        cmdline = quiet
+        initrd = initrd.tar
+
+        [Release]
+        kernel = \\boot\\kernel
+
+        [Debug]
+        kernel = \\boot\\kernel-debug
+        cmdline = quiet noahci
+
+        [Fallback]
+        initrd = fallback.tar
+    ";
+    let config = BootConfig::parse(text).unwrap();
+    let entry = |title: &str, kernel_path: &str, initrd_path: &str, cmdline: &str| BootEntry {
+        title: title.to_string(),
+        kernel_path: kernel_path.to_string(),
+        kernel_sha256: None,
+        initrd_path: Some(initrd_path.to_string()),
+        cmdline: cmdline.to_string(),
+    };
+
+    assert_eq!(config.default_entry, 1);
+    assert_eq!(config.entries, [
+        entry("Release", "\\boot\\kernel", "initrd.tar", "quiet"),
+        entry("Debug", "\\boot\\kernel-debug", "initrd.tar", "quiet noahci"),
+        entry("Fallback", "kernel", "fallback.tar", "quiet"),
+    ]);
+}
+
+#[test]
+fn parses_named_video_modes() {
+    let mode = |text| BootConfig::parse(text).unwrap().video_mode;
+
+    assert_eq!(mode(""), VideoMode::Current);
+    assert_eq!(mode("resolution = current"), VideoMode::Current);
+    assert_eq!(mode("resolution = largest"), VideoMode::Largest);
+    assert_eq!(mode("resolution = native"), VideoMode::Native);
+    assert_eq!(mode("resolution = 800 x 600"), VideoMode::Resolution(800, 600));
+}
+
+#[test]
+fn rounds_stack_size_up_to_pages() {
+    let stack_size = |text| BootConfig::parse(text).unwrap().stack_size;
+
+    assert_eq!(stack_size(""), 256 * 1024);
+    assert_eq!(stack_size("stack_size = 1"), 4096);
+    assert_eq!(stack_size("stack_size = 10000"), 3 * 4096);
+    assert_eq!(stack_size("stack_size = 2M"), 2 * 1024 * 1024);
+    assert_eq!(stack_size("stack_size = 16 k"), 16 * 1024);
+}
@@ -0,0 +1,61 @@
+use formats::edid::{preferred_resolution, EdidError, EDID_BLOCK_SIZE};
+
+/// A base block whose preferred timing is `width`x`height`
+fn edid(width: usize, height: usize) -> Vec<u8> {
+    let mut block = vec![0u8; EDID_BLOCK_SIZE];
+    block[..8].copy_from_slice(&[0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00]);
+    block[8..10].copy_from_slice(&[0x4c, 0x2d]);
+    block[18] = 1;
+    block[19] = 4;
+
+    // 148.5 MHz pixel clock, in 10 kHz units
+    block[54..56].copy_from_slice(&14850u16.to_le_bytes());
+    block[56] = width as u8;
+    block[58] = ((width >> 8) as u8) << 4;
+    block[59] = height as u8;
+    block[61] = ((height >> 8) as u8) << 4;
+
+    fix_checksum(&mut block);
+    block
+}
+
+fn fix_checksum(block: &mut [u8]) {
+    block[127] = 0;
+    let sum = block.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+    block[127] = 0u8.wrapping_sub(sum);
+}
+
+#[test]
+fn reads_the_preferred_timing() {
+    assert_eq!(preferred_resolution(&edid(1920, 1080)), Ok((1920, 1080)));
+    assert_eq!(preferred_resolution(&edid(3840, 2160)), Ok((3840, 2160)));
+    assert_eq!(preferred_resolution(&edid(640, 480)), Ok((640, 480)));
+}
+
+#[test]
+fn ignores_extension_blocks() {
+    let mut data = edid(2560, 1440);
+    data.extend_from_slice(&[0x02; EDID_BLOCK_SIZE]);
+    assert_eq!(preferred_resolution(&data), Ok((2560, 1440)));
+}
+
+#[test]
+fn rejects_bad_blocks() {
+    let good = edid(1920, 1080);
+    assert_eq!(preferred_resolution(&good[..100]), Err(EdidError::Truncated));
+
+    let mut bad_header = good.clone();
+    bad_header[0] = 0xff;
+    assert_eq!(preferred_resolution(&bad_header), Err(EdidError::BadHeader));
+
+    let mut bad_checksum = good.clone();
+    bad_checksum[56] ^= 1;
+    assert_eq!(preferred_resolution(&bad_checksum), Err(EdidError::BadChecksum));
+
+    // A zero pixel clock marks a display descriptor, not a timing
+    let mut no_timing = good;
+    no_timing[54] = 0;
+    no_timing[55] = 0;
+    fix_checksum(&mut no_timing);
+    assert_eq!(preferred_resolution(&no_timing), Err(EdidError::NoPreferredTiming));
+}
@@ -0,0 +1,241 @@
+use formats::elf::{ELFClass, ELFHeader, ElfError, Endianness, ISA, ObjectFileType, ProgramHeaderType, Relocation, R_X86_64_RELATIVE, SHT_STRTAB, SHT_SYMTAB};
+
+// Built from fixtures/minimal.s with:
+//   as --64 -o minimal.o minimal.s
+//   ld -static -nostdlib -z max-page-size=0x1000 -z noexecstack -o minimal.elf minimal.o
+const MINIMAL: &[u8] = include_bytes!("fixtures/minimal.elf");
+
+// Built from fixtures/pie.s with:
+//   as --64 -o pie.o pie.s
+//   ld -pie --no-dynamic-linker -nostdlib -z max-page-size=0x1000 -z noexecstack -z norelro -o pie.elf pie.o
+const PIE: &[u8] = include_bytes!("fixtures/pie.elf");
+const PIE_DYNAMIC_PHDR: usize = 4;
+const DT_RELA: u64 = 7;
+
+const PHDR_TABLE: usize = 64;
+const PHDR_SIZE: usize = 56;
+const SHDR_SIZE: usize = 64;
+const PIE_SYMTAB_SECTION: usize = 11;
+
+fn patched(patch: impl FnOnce(&mut Vec<u8>)) -> Vec<u8> {
+    patched_from(MINIMAL, patch)
+}
+
+fn patched_from(original: &[u8], patch: impl FnOnce(&mut Vec<u8>)) -> Vec<u8> {
+    let mut data = original.to_vec();
+    patch(&mut data);
+    data
+}
+
+/// File offset of the PIE fixture's dynamic entry with the given tag
+fn pie_dynamic_entry(tag: u64) -> usize {
+    let dynamic = read_u64(PIE, PHDR_TABLE + PIE_DYNAMIC_PHDR * PHDR_SIZE + 8) as usize;
+    (dynamic..).step_by(16)
+        .find(|&entry| read_u64(PIE, entry) == tag)
+        .unwrap()
+}
+
+fn write_u64(data: &mut [u8], offset: usize, value: u64) {
+    data[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+#[test]
+fn parses_minimal_executable() {
+    let elf = ELFHeader::make(MINIMAL).expect("fixture should parse");
+
+    assert_eq!(elf.elf_identity.class, ELFClass::X64);
+    assert_eq!(elf.elf_identity.endianness, Endianness::Little);
+    assert_eq!(elf.e_type, ObjectFileType::Executable);
+    assert_eq!(elf.isa, ISA::AMD64);
+    assert_eq!(elf.entry_function, 0x401000);
+    assert_eq!(elf.program_headers.len(), 5);
+    assert_eq!(elf.load_segments().count(), 4);
+    assert!(elf.program_headers.iter().any(|ph| matches!(ph.p_type, ProgramHeaderType::Other(0x6474e551))));
+}
+
+#[test]
+fn load_segments_are_page_rounded() {
+    let elf = ELFHeader::make(MINIMAL).unwrap();
+    let data = elf.load_segments().last().unwrap();
+
+    assert_eq!(data.non_aligned_vaddr, 0x403006);
+    assert_eq!(data.aligned_vaddr, 0x403000);
+    assert_eq!(data.vaddr_end, 0x405000);
+    assert_eq!(data.page_count, 2);
+    assert!(data.file_size < data.memory_size);
+    assert_eq!(data.file_bytes(MINIMAL), Some(&42u64.to_le_bytes()[..]));
+    assert_eq!(data.file_bytes(&MINIMAL[..data.offset]), None);
+}
+
+#[test]
+fn every_truncation_is_an_error() {
+    let table_end = PHDR_TABLE + 5 * PHDR_SIZE;
+    for len in 0..table_end {
+        assert!(ELFHeader::make(&MINIMAL[..len]).is_err(), "prefix of {} bytes parsed", len);
+    }
+}
+
+#[test]
+fn rejects_bad_identity() {
+    let bad_magic = patched(|d| d[0] = 0);
+    assert!(matches!(ELFHeader::make(&bad_magic), Err(ElfError::BadMagic)));
+
+    let elf32 = patched(|d| d[4] = 1);
+    assert!(matches!(ELFHeader::make(&elf32), Err(ElfError::UnsupportedClass(1))));
+
+    let big_endian = patched(|d| d[5] = 2);
+    assert!(matches!(ELFHeader::make(&big_endian), Err(ElfError::UnsupportedEndianness(2))));
+}
+
+#[test]
+fn rejects_wrong_machine_and_type() {
+    let arm = patched(|d| d[18] = 0x28);
+    assert!(matches!(ELFHeader::make(&arm), Err(ElfError::UnsupportedIsa(0x28))));
+
+    let relocatable = patched(|d| d[16] = 1);
+    assert!(matches!(ELFHeader::make(&relocatable), Err(ElfError::UnsupportedType(1))));
+}
+
+#[test]
+fn rejects_program_header_table_past_end() {
+    let data = patched(|d| write_u64(d, 32, MINIMAL.len() as u64));
+    assert!(matches!(ELFHeader::make(&data), Err(ElfError::ProgramHeaderTableOutOfBounds)));
+
+    let overflow = patched(|d| write_u64(d, 32, u64::MAX - 8));
+    assert!(matches!(ELFHeader::make(&overflow), Err(ElfError::ProgramHeaderTableOutOfBounds)));
+}
+
+#[test]
+fn rejects_bad_segments() {
+    let data_phdr = PHDR_TABLE + 3 * PHDR_SIZE;
+
+    let out_of_bounds = patched(|d| write_u64(d, data_phdr + 8, MINIMAL.len() as u64));
+    assert!(matches!(ELFHeader::make(&out_of_bounds), Err(ElfError::SegmentOutOfBounds(3))));
+
+    let file_larger_than_memory = patched(|d| write_u64(d, data_phdr + 40, 1));
+    assert!(matches!(ELFHeader::make(&file_larger_than_memory), Err(ElfError::SegmentFileSizeTooLarge(3))));
+
+    let wraps = patched(|d| write_u64(d, data_phdr + 16, u64::MAX - 4));
+    assert!(matches!(ELFHeader::make(&wraps), Err(ElfError::SegmentAddressOverflow(3))));
+}
+
+#[test]
+fn rejects_overlapping_segments() {
+    let text_phdr = PHDR_TABLE + PHDR_SIZE;
+    let rodata_phdr = PHDR_TABLE + 2 * PHDR_SIZE;
+    let data = patched(|d| {
+        let text_vaddr = read_u64(d, text_phdr + 16);
+        write_u64(d, rodata_phdr + 16, text_vaddr);
+    });
+
+    assert!(matches!(ELFHeader::make(&data), Err(ElfError::OverlappingSegments(1, 2))));
+}
+
+#[test]
+fn rejects_entry_outside_code() {
+    let into_data = patched(|d| write_u64(d, 24, 0x403006));
+    assert!(matches!(ELFHeader::make(&into_data), Err(ElfError::EntryOutsideSegments(0x403006))));
+}
+
+#[test]
+fn executables_have_no_relocations() {
+    let elf = ELFHeader::make(MINIMAL).unwrap();
+    assert!(!elf.is_position_independent());
+    assert!(elf.relocations.is_empty());
+}
+
+#[test]
+fn parses_pie_relocations() {
+    let elf = ELFHeader::make(PIE).expect("PIE fixture should parse");
+
+    assert_eq!(elf.e_type, ObjectFileType::SharedObject);
+    assert!(elf.is_position_independent());
+    assert_eq!(elf.relocations, [
+        Relocation { offset: 0x3118, r_type: R_X86_64_RELATIVE, symbol: 0, addend: 0x2000 },
+        Relocation { offset: 0x3120, r_type: R_X86_64_RELATIVE, symbol: 0, addend: 0x1001 },
+    ]);
+}
+
+#[test]
+fn rejects_rel_tables() {
+    let data = patched_from(PIE, |d| write_u64(d, pie_dynamic_entry(DT_RELA), 17));
+    assert!(matches!(ELFHeader::make(&data), Err(ElfError::UnsupportedRelTable)));
+}
+
+#[test]
+fn rejects_bad_relocation_tables() {
+    let rela = pie_dynamic_entry(DT_RELA);
+
+    let unmapped = patched_from(PIE, |d| write_u64(d, rela + 8, 0x100000));
+    assert!(matches!(ELFHeader::make(&unmapped), Err(ElfError::RelocationTableOutOfBounds)));
+
+    // The fixture's first PT_LOAD maps the file at address 0, so the table's address is its file offset
+    let table = read_u64(PIE, rela + 8) as usize;
+    let outside = patched_from(PIE, |d| write_u64(d, table, 0x100000));
+    assert!(matches!(ELFHeader::make(&outside), Err(ElfError::RelocationOutsideSegments(0x100000))));
+}
+
+/// File offset of the PIE fixture's section header at `index`
+fn pie_section_header(index: usize) -> usize {
+    read_u64(PIE, 40) as usize + index * SHDR_SIZE
+}
+
+#[test]
+fn parses_section_headers() {
+    let elf = ELFHeader::make(PIE).unwrap();
+    let sections = elf.section_headers(PIE).unwrap();
+
+    assert_eq!(sections.len(), 14);
+    let symtab = &sections[PIE_SYMTAB_SECTION];
+    assert_eq!(symtab.sh_type, SHT_SYMTAB);
+    assert_eq!(symtab.entry_size, 24);
+    assert_eq!(sections[symtab.link as usize].sh_type, SHT_STRTAB);
+}
+
+#[test]
+fn resolves_pie_symbols() {
+    let elf = ELFHeader::make(PIE).unwrap();
+    let symbols = elf.symbol_table(PIE).unwrap().expect("the fixture is not stripped");
+
+    assert_eq!(symbols.iter().count(), 10);
+    assert_eq!(symbols.find("_start").unwrap().value, 0x1000);
+    assert_eq!(symbols.find("missing"), None);
+
+    let lookup = |address| symbols.lookup(address).map(|(symbol, offset)| (symbol.name, offset));
+    assert_eq!(lookup(0x1000), Some(("_start", 0)));
+    assert_eq!(lookup(0x1004), Some(("_start", 4)));
+    assert_eq!(lookup(0x2008), Some(("message", 8)));
+    assert_eq!(lookup(0x3120), Some(("pointers", 8)));
+    assert_eq!(lookup(0x10), None);
+}
+
+#[test]
+fn stripped_files_have_no_symbols() {
+    let no_sections = patched_from(PIE, |d| d[60..62].copy_from_slice(&0u16.to_le_bytes()));
+    let elf = ELFHeader::make(&no_sections).unwrap();
+    assert!(elf.section_headers(&no_sections).unwrap().is_empty());
+    assert!(elf.symbol_table(&no_sections).unwrap().is_none());
+
+    assert!(ELFHeader::make(MINIMAL).unwrap().symbol_table(MINIMAL).unwrap().is_some());
+}
+
+#[test]
+fn rejects_bad_section_tables() {
+    // Loading does not look at sections, so only the section accessors fail
+    let outside = patched_from(PIE, |d| write_u64(d, 40, 0x100000));
+    let elf = ELFHeader::make(&outside).unwrap();
+    assert!(matches!(elf.section_headers(&outside), Err(ElfError::SectionHeaderTableOutOfBounds)));
+
+    let symtab = pie_section_header(PIE_SYMTAB_SECTION);
+    let bad_link = patched_from(PIE, |d| d[symtab + 40..symtab + 44].copy_from_slice(&0u32.to_le_bytes()));
+    let elf = ELFHeader::make(&bad_link).unwrap();
+    assert!(matches!(elf.symbol_table(&bad_link), Err(ElfError::BadStringTableLink(0))));
+
+    let bad_size = patched_from(PIE, |d| write_u64(d, symtab + 32, 0x100000));
+    let elf = ELFHeader::make(&bad_size).unwrap();
+    assert!(matches!(elf.symbol_table(&bad_size), Err(ElfError::SectionOutOfBounds(PIE_SYMTAB_SECTION))));
+}
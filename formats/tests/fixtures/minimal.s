@@ -0,0 +1,17 @@
+    .globl _start
+    .text
+_start:
+    hlt
+    jmp _start
+
+    .section .rodata
+message:
+    .asciz "sorix"
+
+    .data
+counter:
+    .quad 42
+
+    .bss
+buffer:
+    .skip 4096
@@ -0,0 +1,15 @@
+    .globl _start
+    .text
+_start:
+    lea message(%rip), %rax
+    hlt
+    jmp _start
+
+    .section .rodata
+message:
+    .asciz "sorix"
+
+    .data
+pointers:
+    .quad message
+    .quad _start + 1
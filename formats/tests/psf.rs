@@ -0,0 +1,35 @@
+use formats::psf::PsfFont;
+
+const FONT: &[u8] = include_bytes!("fixtures/font.psf");
+
+#[test]
+fn parses_font() {
+    let font = PsfFont::from_bytes(FONT).expect("fixture should parse");
+
+    assert!(font.glyph_height() > 0);
+    assert!(font.glyph_count() == 256 || font.glyph_count() == 512);
+    assert_eq!(font.glyph_for(b'A').len(), font.glyph_height());
+    assert!(font.glyph_for(b'A').iter().any(|row| *row != 0));
+    assert!(font.glyph_for(b' ').iter().all(|row| *row == 0));
+}
+
+#[test]
+fn rejects_bad_magic() {
+    let mut data = FONT.to_vec();
+    data[1] = 0x05;
+    assert!(PsfFont::from_bytes(&data).is_none());
+}
+
+#[test]
+fn rejects_truncated_glyphs() {
+    for len in [0, 3, 4, FONT.len() / 2] {
+        assert!(PsfFont::from_bytes(&FONT[..len]).is_none(), "prefix of {} bytes parsed", len);
+    }
+}
+
+#[test]
+fn rejects_zero_height() {
+    let mut data = FONT.to_vec();
+    data[3] = 0;
+    assert!(PsfFont::from_bytes(&data).is_none());
+}
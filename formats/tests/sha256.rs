@@ -0,0 +1,40 @@
+use formats::sha256::{digest, parse_hex_digest, HexDigest, Sha256};
+
+fn hex(data: &[u8]) -> String {
+    HexDigest(&digest(data)).to_string()
+}
+
+#[test]
+fn matches_the_fips_vectors() {
+    assert_eq!(hex(b""), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+    assert_eq!(hex(b"abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+    assert_eq!(
+        hex(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+        "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+    );
+    assert_eq!(hex(&[b'a'; 1_000_000]), "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0");
+}
+
+#[test]
+fn incremental_updates_match_one_shot() {
+    let data: Vec<u8> = (0..1000u32).map(|i| (i * 7) as u8).collect();
+    for split in [0, 1, 55, 56, 63, 64, 65, 500, 1000] {
+        let mut hasher = Sha256::new();
+        hasher.update(&data[..split]);
+        hasher.update(&data[split..]);
+        assert_eq!(hasher.finalize(), digest(&data), "split at {}", split);
+    }
+}
+
+#[test]
+fn parses_hex_digests() {
+    let text = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+    assert_eq!(parse_hex_digest(text), Some(digest(b"abc")));
+    assert_eq!(parse_hex_digest(&text.to_uppercase()), Some(digest(b"abc")));
+    assert_eq!(parse_hex_digest(&format!("{}  kernel\n", text)), Some(digest(b"abc")));
+
+    assert_eq!(parse_hex_digest(""), None);
+    assert_eq!(parse_hex_digest(&text[1..]), None);
+    assert_eq!(parse_hex_digest(&text.replace('a', "g")), None);
+    assert_eq!(parse_hex_digest(&text.replacen("ba", "+a", 1)), None);
+}
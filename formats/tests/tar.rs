@@ -0,0 +1,61 @@
+use formats::tar::{EntryKind, TarArchive, TarError};
+
+// Built with:
+//   tar --format=ustar --owner=0 --group=0 --numeric-owner --mtime=2025-01-01 --sort=name \
+//       -cf initrd.tar hello.txt fonts
+const INITRD: &[u8] = include_bytes!("fixtures/initrd.tar");
+const FONT: &[u8] = include_bytes!("fixtures/font.psf");
+
+#[test]
+fn lists_entries_in_order() {
+    let archive = TarArchive::new(INITRD);
+    let entries: Vec<_> = archive.entries().map(|e| e.unwrap()).collect();
+
+    let listing: Vec<_> = entries.iter().map(|e| (e.path(), e.kind, e.data.len())).collect();
+    assert_eq!(listing, [
+        ("hello.txt".to_string(), EntryKind::File, 22),
+        ("fonts/".to_string(), EntryKind::Directory, 0),
+        ("fonts/font.psf".to_string(), EntryKind::File, FONT.len()),
+    ]);
+}
+
+#[test]
+fn finds_files_by_path() {
+    let archive = TarArchive::new(INITRD);
+
+    assert_eq!(archive.find("hello.txt"), Some(&b"hello from the initrd\n"[..]));
+    assert_eq!(archive.find("./fonts/font.psf"), Some(FONT));
+    assert_eq!(archive.find("fonts/"), None);
+    assert_eq!(archive.find("missing"), None);
+}
+
+#[test]
+fn empty_archive_has_no_entries() {
+    assert_eq!(TarArchive::new(&[]).entries().count(), 0);
+    assert_eq!(TarArchive::new(&[0; 1024]).entries().count(), 0);
+}
+
+#[test]
+fn truncation_is_an_error() {
+    // Cut into the second file's data
+    let truncated = &INITRD[..3 * 512 + 100];
+    let results: Vec<_> = TarArchive::new(truncated).entries().collect();
+
+    assert_eq!(results.len(), 3);
+    assert!(results[..2].iter().all(|r| r.is_ok()));
+    assert!(matches!(results[2], Err(TarError::Truncated)));
+}
+
+#[test]
+fn rejects_corrupt_headers() {
+    let mut bad_checksum = INITRD.to_vec();
+    bad_checksum[0] = b'j';
+    assert!(matches!(TarArchive::new(&bad_checksum).entries().next(), Some(Err(TarError::BadChecksum))));
+
+    let mut bad_magic = INITRD.to_vec();
+    bad_magic[257] = b'x';
+    assert!(matches!(TarArchive::new(&bad_magic).entries().next(), Some(Err(TarError::BadMagic))));
+
+    // An error ends the walk
+    assert_eq!(TarArchive::new(&bad_magic).entries().count(), 1);
+}
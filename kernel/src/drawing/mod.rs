@@ -1,7 +1,75 @@
 pub mod fonts;
 
+use boot_protocol::FramebufferInfo;
+
 use crate::kernel::Kernel;
 
+/// Where one color channel sits in a pixel, taken from the bootloader's channel mask
+#[derive(Clone, Copy)]
+pub struct Channel {
+    shift: u32,
+    bits: u32,
+}
+
+impl Channel {
+    fn from_mask(mask: u32) -> Self {
+        if mask == 0 {
+            return Self { shift: 0, bits: 0 };
+        }
+
+        Self { shift: mask.trailing_zeros(), bits: mask.count_ones() }
+    }
+
+    /// Scales an 8-bit channel value to the channel's width and moves it into place
+    fn encode(&self, value: u8) -> u32 {
+        let value = value as u32;
+        let scaled = match self.bits {
+            0 => return 0,
+            1..=8 => value >> (8 - self.bits),
+            bits => value << (bits - 8),
+        };
+
+        scaled << self.shift
+    }
+}
+
+pub struct Framebuffer<'a> {
+    pub buffer: &'a mut [u32],
+    pub width: usize,
+    pub height: usize,
+    pub pixels_per_scan_line: usize,
+    pub red: Channel,
+    pub green: Channel,
+    pub blue: Channel,
+}
+
+impl Framebuffer<'_> {
+    /// # Safety
+    /// `info.base` must point to a mapped framebuffer of `info.size` bytes.
+    pub unsafe fn from_info(info: &FramebufferInfo) -> Self {
+        let buffer = unsafe {
+            core::slice::from_raw_parts_mut(info.base, info.size / 4)
+        };
+
+        Self {
+            buffer,
+            width: info.width,
+            height: info.height,
+            pixels_per_scan_line: info.pixels_per_scan_line,
+            red: Channel::from_mask(info.red_mask),
+            green: Channel::from_mask(info.green_mask),
+            blue: Channel::from_mask(info.blue_mask),
+        }
+    }
+
+    /// Converts a 0xRRGGBB color to the framebuffer's pixel format
+    pub fn encode(&self, color: Color) -> u32 {
+        let rgb = color as u32;
+        self.red.encode((rgb >> 16) as u8) | self.green.encode((rgb >> 8) as u8) | self.blue.encode(rgb as u8)
+    }
+}
+
+/// Colors as 0xRRGGBB, see Framebuffer::encode for what ends up in memory
 #[allow(dead_code)]
 #[derive(Clone, Copy)]
 pub enum Color {
@@ -14,10 +82,11 @@ pub enum Color {
 
 impl Kernel<'_> {
     pub fn draw_area(&mut self, width: usize, height: usize, color: Color) {
+        let pixel = self.framebuffer.encode(color);
         for x in 0..width {
             for y in 0..height {
                 let index = y * self.framebuffer.pixels_per_scan_line + x;
-                self.framebuffer.buffer[index] = color as u32;
+                self.framebuffer.buffer[index] = pixel;
             }
         }
     }
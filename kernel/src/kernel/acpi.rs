@@ -0,0 +1,84 @@
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use boot_protocol::BootInfo;
+use formats::acpi::{self, Rsdp, SdtHeader, RSDP_V2_SIZE, SDT_HEADER_SIZE};
+use spin::mutex::Mutex;
+
+use crate::kernel::paging;
+use crate::kprintln;
+
+/// Every valid table the XSDT (or RSDT) points to, by physical address
+static TABLES: Mutex<Vec<(usize, SdtHeader)>> = Mutex::new(Vec::new());
+
+/// Maps a system description table and returns all `length` bytes of it.
+fn table_bytes(phys: usize) -> Option<&'static [u8]> {
+    let header = paging::map_firmware(phys, SDT_HEADER_SIZE) as *const u8;
+    let header = SdtHeader::parse(unsafe { core::slice::from_raw_parts(header, SDT_HEADER_SIZE) }).ok()?;
+
+    let length = header.length as usize;
+    let table = paging::map_firmware(phys, length) as *const u8;
+    Some(unsafe { core::slice::from_raw_parts(table, length) })
+}
+
+/// Validates the RSDP and the root table, and remembers every table they point to.
+/// Must run after paging::init, since firmware tables may need to be mapped.
+pub fn init(boot_info: &BootInfo) {
+    if boot_info.acpi_rsdp == 0 {
+        kprintln!("No ACPI RSDP, skipping ACPI");
+        return;
+    }
+
+    // An ACPI 1.0 RSDP is only 20 bytes, but mapping the 2.0 size is harmless
+    let rsdp = paging::map_firmware(boot_info.acpi_rsdp as usize, RSDP_V2_SIZE) as *const u8;
+    let rsdp = match Rsdp::parse(unsafe { core::slice::from_raw_parts(rsdp, RSDP_V2_SIZE) }) {
+        Ok(r) => r,
+        Err(e) => {
+            kprintln!("Invalid ACPI RSDP at {:#x}: {:?}", boot_info.acpi_rsdp, e);
+            return;
+        }
+    };
+
+    let (root_address, xsdt) = rsdp.root_table();
+    let root = match table_bytes(root_address as usize) {
+        Some(r) => r,
+        None => {
+            kprintln!("Invalid ACPI root table at {:#x}", root_address);
+            return;
+        }
+    };
+    let entries = match acpi::root_table_entries(root, xsdt) {
+        Ok(e) => e,
+        Err(e) => {
+            kprintln!("Invalid ACPI root table at {:#x}: {:?}", root_address, e);
+            return;
+        }
+    };
+
+    let mut tables = TABLES.lock();
+    for address in entries {
+        match table_bytes(address as usize).map(acpi::parse_table) {
+            Some(Ok(header)) => tables.push((address as usize, header)),
+            Some(Err(e)) => {
+                kprintln!("Skipping invalid ACPI table at {:#x}: {:?}", address, e);
+            },
+            None => {
+                kprintln!("Skipping unreadable ACPI table at {:#x}", address);
+            }
+        }
+    }
+
+    kprintln!("ACPI revision {}, {} tables via the {}", rsdp.revision, tables.len(), if xsdt { "XSDT" } else { "RSDT" });
+    for (address, header) in tables.iter() {
+        kprintln!("  {} at {:#x}, {} bytes", header.signature_str(), address, header.length);
+    }
+}
+
+/// Returns the whole first table with the given signature, e.g. `b"APIC"` for the MADT.
+#[allow(dead_code)]
+pub fn find_table(signature: &[u8; 4]) -> Option<&'static [u8]> {
+    let address = TABLES.lock().iter()
+        .find(|(_, header)| &header.signature == signature)
+        .map(|(address, _)| *address)?;
+
+    table_bytes(address)
+}
\ No newline at end of file
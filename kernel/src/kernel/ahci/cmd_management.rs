@@ -1,8 +1,12 @@
 use core::cell::RefCell;
+use core::ptr::read_volatile;
+use core::time::Duration;
 
 use alloc::{rc::Rc, slice, string::{String, ToString}};
 
-use crate::{kernel::{ahci::HbaPort, page_heap::{self, allocate_page, zero_page}}, kserialprint};
+use crate::{kernel::{ahci::{dma_pointer, HbaPort, PORT_CI, PORT_TFD}, frame_allocator::{self, allocate_frame, zero_page}, paging::phys_to_virt, timer::Instant}, kserialprint};
+
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
 
 #[repr(C, packed)]
 pub struct CommandHeader {
@@ -60,10 +64,16 @@ pub fn create_command_header(port_rc: Rc<RefCell<HbaPort>>) {
     let hba_port = port_rc.borrow_mut();
     let slot = 0;
 
-    let ctba = page_heap::allocate_page();
-    zero_page(ctba, None);
+    let ctba = match allocate_frame() {
+        Some(frame) => frame,
+        None => {
+            kserialprint!("Could not allocate AHCI command table: Out of Memory!");
+            return;
+        }
+    };
+    zero_page(phys_to_virt(ctba) as *mut u8, None);
 
-    let clb = hba_port.clb as *mut CommandHeader;
+    let clb = dma_pointer::<CommandHeader>(hba_port.clb, hba_port.clbu);
 
     let cmdheader = unsafe { &mut *clb.add(slot) };
 
@@ -76,9 +86,9 @@ pub fn create_command_header(port_rc: Rc<RefCell<HbaPort>>) {
 
 pub fn setup_command_table(port_rc: Rc<RefCell<HbaPort>>) {
     let hba_port = port_rc.borrow_mut();
-    let cmdheader = unsafe { &mut *(hba_port.clb as *mut CommandHeader) };
+    let cmdheader = unsafe { &mut *dma_pointer::<CommandHeader>(hba_port.clb, hba_port.clbu) };
 
-    let ctba = unsafe { &mut *(cmdheader.ctba as *mut CommandTable) };
+    let ctba = unsafe { &mut *dma_pointer::<CommandTable>(cmdheader.ctba, cmdheader.ctbau) };
     ctba.command_fis = FisRegH2D {
         fis_type: 0x27,
         pm_port: 1 << 7,
@@ -105,11 +115,17 @@ pub fn setup_command_table(port_rc: Rc<RefCell<HbaPort>>) {
 
 pub fn create_prdt_entry(port_rc: Rc<RefCell<HbaPort>>) {
     let hbaport = port_rc.borrow_mut();
-    let cmdheader = unsafe { &mut *(hbaport.clb as *mut CommandHeader) };
-    let ctba = unsafe { &mut *(cmdheader.ctba as *mut CommandTable) };
-
-    let data_buffer = allocate_page();
-    page_heap::zero_page(data_buffer, None);
+    let cmdheader = unsafe { &mut *dma_pointer::<CommandHeader>(hbaport.clb, hbaport.clbu) };
+    let ctba = unsafe { &mut *dma_pointer::<CommandTable>(cmdheader.ctba, cmdheader.ctbau) };
+
+    let data_buffer = match allocate_frame() {
+        Some(frame) => frame,
+        None => {
+            kserialprint!("Could not allocate AHCI data buffer: Out of Memory!");
+            return;
+        }
+    };
+    frame_allocator::zero_page(phys_to_virt(data_buffer) as *mut u8, None);
 
     ctba.prdt_entry[0] = PhysicalRegionDescriptor {
         data_base: data_buffer as u32,
@@ -123,12 +139,13 @@ pub fn create_prdt_entry(port_rc: Rc<RefCell<HbaPort>>) {
     cmdheader.prdbc = 0;
 }
 
-pub fn issue_command(port_rc: Rc<RefCell<HbaPort>>) {
+/// `port_registers` is the port's MMIO register set, from `HbaMem::port_registers`
+pub fn issue_command(port_rc: Rc<RefCell<HbaPort>>, port_registers: usize) {
     let mut hbaport = port_rc.borrow_mut();
     let cmdheader_ptr = hbaport.clb as *const CommandHeader;
-    let cmdheader = unsafe { &*(hbaport.clb as *const CommandHeader) };
+    let cmdheader = unsafe { &*dma_pointer::<CommandHeader>(hbaport.clb, hbaport.clbu) };
 
-    let cmdtable_ptr = cmdheader.ctba as *const CommandTable;
+    let cmdtable_ptr = dma_pointer::<CommandTable>(cmdheader.ctba, cmdheader.ctbau) as *const CommandTable;
     let cmdtable = unsafe { &*(cmdtable_ptr) };
 
     let prdt_entry = &cmdtable.prdt_entry[0];
@@ -150,27 +167,25 @@ pub fn issue_command(port_rc: Rc<RefCell<HbaPort>>) {
 
     kserialprint!("Waiting for Command To Finish");
 
-    let mut success = false;
-    let mut timeout = 900_000_000;
-    // Wait for finish
-    while hbaport.ci & (1 << 0) != 0 && timeout != 0 {
-        if timeout == 0 {
+    // Wait for finish. The HBA clears CI in its own registers, never in the copy in port_rc.
+    let ci = (port_registers + PORT_CI) as *const u32;
+    let deadline = Instant::now() + COMMAND_TIMEOUT;
+    let mut success = true;
+    while unsafe { read_volatile(ci) } & (1 << 0) != 0 {
+        if Instant::now() >= deadline {
+            success = false;
             break;
         }
-
-        timeout -= 1;
-    }
-    kserialprint!("TDF Raw = {:#010b}", hbaport.tfd);
-
-    if timeout > 0 {
-        success = true;
+        core::hint::spin_loop();
     }
+    let tfd = unsafe { read_volatile((port_registers + PORT_TFD) as *const u32) };
+    kserialprint!("TDF Raw = {:#010b}", tfd);
 
     // Also check for error
     if !success {
         kserialprint!("Command Timeout!");
-    } else if hbaport.tfd & 0x88 != 0 {
-        kserialprint!("AHCI Error: Task File Data = {:#x}", hbaport.tfd);
+    } else if tfd & 0x88 != 0 {
+        kserialprint!("AHCI Error: Task File Data = {:#x}", tfd);
     } else {
         kserialprint!("Read completed successfully!");
     }
@@ -178,10 +193,10 @@ pub fn issue_command(port_rc: Rc<RefCell<HbaPort>>) {
 
 pub fn read_data_buffer(port_rc: Rc<RefCell<HbaPort>>) {
     let hbaport = port_rc.borrow_mut();
-    let cmdheader = unsafe { &mut *(hbaport.clb as *mut CommandHeader) };
-    let ctba = unsafe { &mut *(cmdheader.ctba as *mut CommandTable) };
+    let cmdheader = unsafe { &mut *dma_pointer::<CommandHeader>(hbaport.clb, hbaport.clbu) };
+    let ctba = unsafe { &mut *dma_pointer::<CommandTable>(cmdheader.ctba, cmdheader.ctbau) };
 
-    let data_buffer = ctba.prdt_entry[0].data_base as *mut u8;
+    let data_buffer = dma_pointer::<u8>(ctba.prdt_entry[0].data_base, ctba.prdt_entry[0].data_base_upper);
     let buf = unsafe {
         slice::from_raw_parts(data_buffer as *const u8, 512)
     };
@@ -194,8 +209,8 @@ pub fn read_data_buffer(port_rc: Rc<RefCell<HbaPort>>) {
 
 pub fn check_integrity(port_rc: Rc<RefCell<HbaPort>>) {
     let hba_port = port_rc.borrow();
-    let cmd_header = unsafe { &mut *(hba_port.clb as *mut CommandHeader) };
-    let cmd_table = unsafe { &mut *(cmd_header.ctba as *mut CommandTable) };
+    let cmd_header = unsafe { &mut *dma_pointer::<CommandHeader>(hba_port.clb, hba_port.clbu) };
+    let cmd_table = unsafe { &mut *dma_pointer::<CommandTable>(cmd_header.ctba, cmd_header.ctbau) };
 
     let flags = cmd_header.flags;
     let prdt_length = cmd_header.prdt_length;
@@ -7,10 +7,17 @@ use crate::alloc::string::ToString;
 use alloc::rc::Rc;
 use alloc::vec::Vec;
 use alloc::boxed::Box;
-use crate::kernel::page_heap::{self, allocate_page};
+use crate::kernel::frame_allocator::{self, allocate_frame};
+use crate::kernel::paging::{self, phys_to_virt};
 use crate::kprintln;
 use crate::kernel::pci::*;
 
+const HBA_MEM_SIZE: usize = 0x1100; // Generic host control registers plus 32 port register sets
+const PORT_REGISTERS_OFFSET: usize = 0x100;
+const PORT_REGISTERS_SIZE: usize = 0x80;
+pub const PORT_TFD: usize = 0x20;
+pub const PORT_CI: usize = 0x38;
+
 #[derive(Default)]
 #[repr(C)]
 pub struct HbaMem {
@@ -27,6 +34,14 @@ pub struct HbaMem {
     pub bohc: u32,
     //_reserved: [u8; 0xA0 - 0x2C], // Pad to port list
     pub ports: Vec<Rc<RefCell<HbaPort>>>, // Max of 32 ports
+    pub mmio: usize, // Where the registers themselves are mapped
+}
+
+impl HbaMem {
+    /// Address of port `index`'s live registers, which `ports` only holds a copy of
+    pub fn port_registers(&self, index: usize) -> usize {
+        self.mmio + PORT_REGISTERS_OFFSET + index * PORT_REGISTERS_SIZE
+    }
 }
 
 #[derive(Default, Copy, Clone)]
@@ -68,7 +83,12 @@ pub fn read_bar5(bus: u8, device: u8, function: u8) -> u32 {
     mmio_base
 }
 
-fn read_hba_mem_volatile(mmio: u32) -> Box<HbaMem> {
+/// CPU pointer for a DMA address the HBA sees split across a lower and an upper register
+pub fn dma_pointer<T>(lower: u32, upper: u32) -> *mut T {
+    phys_to_virt(((upper as usize) << 32) | lower as usize) as *mut T
+}
+
+fn read_hba_mem_volatile(mmio: usize) -> Box<HbaMem> {
     let regs_ptr = mmio as *const u32;
     let mut fields = [0u32; 11];
     for i in 0..10 {
@@ -89,7 +109,8 @@ fn read_hba_mem_volatile(mmio: u32) -> Box<HbaMem> {
         em_ctl: fields[8],
         cap2: fields[9],
         bohc: fields[10],
-        ports: Vec::with_capacity(32)
+        ports: Vec::with_capacity(32),
+        mmio,
      };
 
      let mut hba_box = Box::new(hba);
@@ -109,7 +130,7 @@ fn read_hba_mem_volatile(mmio: u32) -> Box<HbaMem> {
 }
 
 #[allow(unused_assignments)]
-fn read_hba_ports_volatile(mmio: u32) -> Rc<RefCell<HbaPort>>{
+fn read_hba_ports_volatile(mmio: usize) -> Rc<RefCell<HbaPort>>{
     let ports_base = (mmio + 0x100) as *const u32;
     let mut fields = [0u32; 17];
 
@@ -161,7 +182,7 @@ pub fn scan_pci_for_ahci() -> Option<Box<HbaMem>> {
                     found = true;
                     kprintln!("Found AHCI controller at {}:{}:{}", 0, device, 0);
 
-                    let mmio = read_bar5(bus, device, function);
+                    let mmio = paging::map_mmio(read_bar5(bus, device, function) as usize, HBA_MEM_SIZE);
 
                     let hba = read_hba_mem_volatile(mmio);
                     kprintln!("HBA CAP: {:#x}, GHC: {:#x}, PI (Ports Implemented): {:#x}", hba.cap, hba.ghc, hba.pi);
@@ -237,8 +258,14 @@ pub fn stop_command_engine(port_rc: Rc<RefCell<HbaPort>>) {
 pub fn initialize_port(port_rc: Rc<RefCell<HbaPort>>) {
     let mut port = port_rc.borrow_mut();
 
-    let clb = allocate_page();
-    let fb = allocate_page();
+    // Both live in their own frame, which covers the 1 KB alignment of the command list and 256 B of the FIS area
+    let (clb, fb) = match (allocate_frame(), allocate_frame()) {
+        (Some(clb), Some(fb)) => (clb, fb),
+        _ => {
+            kprintln!("Could not allocate AHCI command list or FIS pages: Out of Memory!");
+            return;
+        }
+    };
 
     port.clb = clb as u32;
     port.clbu = (clb as u64 >> 32) as u32;
@@ -246,6 +273,6 @@ pub fn initialize_port(port_rc: Rc<RefCell<HbaPort>>) {
     port.fb = fb as u32;
     port.fbu = (fb as u64 >> 32) as u32;
 
-    page_heap::zero_page(clb, 1024.into());
-    page_heap::zero_page(fb, 256.into());
+    frame_allocator::zero_page(phys_to_virt(clb) as *mut u8, 1024.into());
+    frame_allocator::zero_page(phys_to_virt(fb) as *mut u8, 256.into());
 }
\ No newline at end of file
@@ -0,0 +1,360 @@
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::arch::x86_64::__cpuid;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use formats::acpi::{self, Madt, MadtEntry};
+use spin::mutex::Mutex;
+
+use crate::kernel::{acpi as kernel_acpi, cpu, idt, paging, pic};
+use crate::kprintln;
+
+// Vectors handed out to IRQ handlers. Below them are the exceptions and the remapped legacy PIC.
+const FIRST_VECTOR: u8 = pic::VECTOR_END;
+pub const SPURIOUS_VECTOR: u8 = 0xFF;
+
+const IA32_APIC_BASE: u32 = 0x1B;
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+const APIC_BASE_X2APIC: u64 = 1 << 10;
+const APIC_BASE_ADDRESS_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+const X2APIC_MSR_BASE: u32 = 0x800;
+
+// Local APIC registers, as xAPIC MMIO offsets. In x2APIC mode each is the MSR X2APIC_MSR_BASE + offset / 16.
+const LAPIC_ID: u32 = 0x20;
+const LAPIC_VERSION: u32 = 0x30;
+const LAPIC_TPR: u32 = 0x80;
+const LAPIC_EOI: u32 = 0xB0;
+const LAPIC_SVR: u32 = 0xF0;
+const LAPIC_LVT_LINT0: u32 = 0x350;
+const LAPIC_LVT_LINT1: u32 = 0x360;
+const LAPIC_LVT_TIMER: u32 = 0x320;
+const LAPIC_TIMER_INITIAL_COUNT: u32 = 0x380;
+const LAPIC_TIMER_CURRENT_COUNT: u32 = 0x390;
+const LAPIC_TIMER_DIVIDE: u32 = 0x3E0;
+
+const SVR_ENABLE: u32 = 1 << 8;
+const LVT_MASKED: u32 = 1 << 16;
+const LVT_NMI: u32 = 0b100 << 8;
+const LVT_TIMER_PERIODIC: u32 = 1 << 17;
+const TIMER_DIVIDE_BY_16: u32 = 0b0011;
+
+// I/O APIC registers, selected through IOREGSEL and accessed through IOWIN
+const IOAPIC_IOREGSEL: usize = 0x00;
+const IOAPIC_IOWIN: usize = 0x10;
+const IOAPIC_VERSION: u32 = 0x01;
+const IOAPIC_REDIRECTION_TABLE: u32 = 0x10;
+
+const REDIRECTION_ACTIVE_LOW: u64 = 1 << 13;
+const REDIRECTION_LEVEL: u64 = 1 << 15;
+const REDIRECTION_MASKED: u64 = 1 << 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Trigger {
+    Edge,
+    Level,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Polarity {
+    ActiveHigh,
+    ActiveLow,
+}
+
+#[allow(dead_code)]
+#[derive(Debug)]
+pub enum ApicError {
+    NotInitialized,
+    NoIoApicForGsi(u32),
+    OutOfVectors,
+}
+
+struct IoApic {
+    id: u8,
+    registers: usize, // Virtual address of IOREGSEL
+    gsi_base: u32,
+    redirection_entries: u32,
+}
+
+struct InterruptOverride {
+    irq: u8,
+    gsi: u32,
+    flags: u16,
+}
+
+struct InterruptControllers {
+    io_apics: Vec<IoApic>,
+    overrides: Vec<InterruptOverride>,
+    bsp_apic_id: u32,
+    next_vector: u8,
+}
+
+// The local APIC is reached from interrupt handlers for the EOI, so its mode lives in atomics instead of behind the Mutex
+static X2APIC: AtomicBool = AtomicBool::new(false);
+static LAPIC_REGISTERS: AtomicUsize = AtomicUsize::new(0); // xAPIC MMIO base, 0 until init
+static CONTROLLERS: Mutex<Option<InterruptControllers>> = Mutex::new(None);
+
+fn lapic_read(register: u32) -> u32 {
+    if X2APIC.load(Ordering::Relaxed) {
+        return cpu::rdmsr(X2APIC_MSR_BASE + register / 16) as u32;
+    }
+
+    let base = LAPIC_REGISTERS.load(Ordering::Relaxed);
+    unsafe { core::ptr::read_volatile((base + register as usize) as *const u32) }
+}
+
+fn lapic_write(register: u32, value: u32) {
+    if X2APIC.load(Ordering::Relaxed) {
+        unsafe {
+            cpu::wrmsr(X2APIC_MSR_BASE + register / 16, value as u64);
+        }
+        return;
+    }
+
+    let base = LAPIC_REGISTERS.load(Ordering::Relaxed);
+    unsafe {
+        core::ptr::write_volatile((base + register as usize) as *mut u32, value);
+    }
+}
+
+impl InterruptControllers {
+    fn allocate_vector(&mut self, handler: fn()) -> Result<u8, ApicError> {
+        let vector = self.next_vector;
+        if vector == SPURIOUS_VECTOR {
+            return Err(ApicError::OutOfVectors);
+        }
+        self.next_vector += 1;
+        idt::set_irq_handler(vector, handler);
+
+        Ok(vector)
+    }
+}
+
+impl IoApic {
+    fn read(&self, register: u32) -> u32 {
+        unsafe {
+            core::ptr::write_volatile((self.registers + IOAPIC_IOREGSEL) as *mut u32, register);
+            core::ptr::read_volatile((self.registers + IOAPIC_IOWIN) as *const u32)
+        }
+    }
+
+    fn write(&self, register: u32, value: u32) {
+        unsafe {
+            core::ptr::write_volatile((self.registers + IOAPIC_IOREGSEL) as *mut u32, register);
+            core::ptr::write_volatile((self.registers + IOAPIC_IOWIN) as *mut u32, value);
+        }
+    }
+
+    fn handles(&self, gsi: u32) -> bool {
+        gsi >= self.gsi_base && gsi < self.gsi_base + self.redirection_entries
+    }
+
+    fn set_redirection(&self, gsi: u32, entry: u64) {
+        let register = IOAPIC_REDIRECTION_TABLE + (gsi - self.gsi_base) * 2;
+        // Mask first, so the entry is never live while half written
+        self.write(register, REDIRECTION_MASKED as u32);
+        self.write(register + 1, (entry >> 32) as u32);
+        self.write(register, entry as u32);
+    }
+}
+
+/// Programs a local interrupt pin from a MADT NMI entry
+fn set_lint_nmi(lint: u8, flags: u16) {
+    let mut lvt = LVT_NMI;
+    if flags & acpi::INTI_POLARITY_MASK == acpi::INTI_ACTIVE_LOW {
+        lvt |= 1 << 13;
+    }
+    if flags & acpi::INTI_TRIGGER_MASK == acpi::INTI_LEVEL {
+        lvt |= 1 << 15;
+    }
+
+    match lint {
+        0 => lapic_write(LAPIC_LVT_LINT0, lvt),
+        1 => lapic_write(LAPIC_LVT_LINT1, lvt),
+        _ => {
+            kprintln!("Ignoring an NMI on unknown LINT{}", lint);
+        },
+    }
+}
+
+/// Switches the local APIC on, in x2APIC mode when the CPU has it, and returns its ID
+fn enable_local_apic() -> u32 {
+    let x2apic = __cpuid(1).ecx & (1 << 21) != 0;
+    let base = cpu::rdmsr(IA32_APIC_BASE) | APIC_BASE_ENABLE;
+    // x2APIC mode can only be entered from an enabled xAPIC, so this takes two writes from a disabled APIC
+    unsafe {
+        cpu::wrmsr(IA32_APIC_BASE, base);
+        if x2apic {
+            cpu::wrmsr(IA32_APIC_BASE, base | APIC_BASE_X2APIC);
+        }
+    }
+    if !x2apic {
+        let physical = (base & APIC_BASE_ADDRESS_MASK) as usize;
+        LAPIC_REGISTERS.store(paging::map_mmio(physical, paging::PAGE_SIZE), Ordering::Relaxed);
+    }
+    X2APIC.store(x2apic, Ordering::Relaxed);
+
+    // Accept every priority, mask the legacy pins until the MADT says otherwise, then software-enable
+    lapic_write(LAPIC_TPR, 0);
+    lapic_write(LAPIC_LVT_LINT0, LVT_MASKED);
+    lapic_write(LAPIC_LVT_LINT1, LVT_MASKED);
+    lapic_write(LAPIC_SVR, SVR_ENABLE | SPURIOUS_VECTOR as u32);
+
+    let id = lapic_read(LAPIC_ID);
+    let id = if x2apic { id } else { id >> 24 };
+    kprintln!("Local APIC {} enabled in {} mode, version {:#x}",
+        id, if x2apic { "x2APIC" } else { "xAPIC" }, lapic_read(LAPIC_VERSION) & 0xFF);
+    id
+}
+
+/// Remaps and masks the legacy PIC, enables the local APIC and masks every I/O APIC input.
+/// Returns false, leaving interrupts off, if there is no APIC or no MADT describing it.
+/// Must run after acpi::init and idt::init.
+pub fn init() -> bool {
+    pic::remap_and_mask();
+
+    if __cpuid(1).edx & (1 << 9) == 0 {
+        kprintln!("CPU has no local APIC, interrupts stay off");
+        return false;
+    }
+    let madt = match kernel_acpi::find_table(acpi::MADT_SIGNATURE).map(Madt::parse) {
+        Some(Ok(madt)) => madt,
+        Some(Err(e)) => {
+            kprintln!("Invalid MADT: {:?}, interrupts stay off", e);
+            return false;
+        },
+        None => {
+            kprintln!("No MADT, interrupts stay off");
+            return false;
+        }
+    };
+
+    let bsp_apic_id = enable_local_apic();
+    // NMI entries name processors by ACPI processor UID, not APIC ID, so look up the boot CPU's UID first
+    let bsp_uid = madt.entries().find_map(|entry| match entry {
+        MadtEntry::LocalApic { processor_id, apic_id, .. } if apic_id as u32 == bsp_apic_id => Some(processor_id as u32),
+        MadtEntry::LocalX2Apic { apic_id, processor_uid, .. } if apic_id == bsp_apic_id => Some(processor_uid),
+        _ => None,
+    });
+
+    let mut controllers = InterruptControllers {
+        io_apics: Vec::new(),
+        overrides: Vec::new(),
+        bsp_apic_id,
+        next_vector: FIRST_VECTOR,
+    };
+    let mut processors = 0;
+    for entry in madt.entries() {
+        match entry {
+            MadtEntry::LocalApic { flags, .. } | MadtEntry::LocalX2Apic { flags, .. } if flags & 1 != 0 => processors += 1,
+            MadtEntry::IoApic { id, address, gsi_base } => {
+                let registers = paging::map_mmio(address as usize, paging::PAGE_SIZE);
+                let mut io_apic = IoApic { id, registers, gsi_base, redirection_entries: 0 };
+                io_apic.redirection_entries = ((io_apic.read(IOAPIC_VERSION) >> 16) & 0xFF) + 1;
+                for gsi in gsi_base..gsi_base + io_apic.redirection_entries {
+                    io_apic.set_redirection(gsi, REDIRECTION_MASKED);
+                }
+                controllers.io_apics.push(io_apic);
+            },
+            MadtEntry::InterruptSourceOverride { source, gsi, flags, .. } => {
+                controllers.overrides.push(InterruptOverride { irq: source, gsi, flags });
+            },
+            MadtEntry::LocalApicNmi { processor_id, flags, lint } if processor_id == 0xFF || Some(processor_id as u32) == bsp_uid => {
+                set_lint_nmi(lint, flags);
+            },
+            MadtEntry::LocalX2ApicNmi { processor_uid, flags, lint } if processor_uid == u32::MAX || Some(processor_uid) == bsp_uid => {
+                set_lint_nmi(lint, flags);
+            },
+            _ => {},
+        }
+    }
+
+    kprintln!("MADT: {} processors, {} I/O APICs, {} interrupt overrides",
+        processors, controllers.io_apics.len(), controllers.overrides.len());
+    for io_apic in &controllers.io_apics {
+        kprintln!("  I/O APIC {} at {:#x}, GSIs {}-{}",
+            io_apic.id, io_apic.registers, io_apic.gsi_base, io_apic.gsi_base + io_apic.redirection_entries - 1);
+    }
+
+    *CONTROLLERS.lock() = Some(controllers);
+    true
+}
+
+/// Sends `handler` the global system interrupt `gsi` on a fresh vector, delivered to the boot CPU, and returns the vector
+#[allow(dead_code)]
+pub fn route_gsi(gsi: u32, trigger: Trigger, polarity: Polarity, handler: fn()) -> Result<u8, ApicError> {
+    let mut guard = CONTROLLERS.lock();
+    let controllers = guard.as_mut().ok_or(ApicError::NotInitialized)?;
+    let index = controllers.io_apics.iter().position(|io_apic| io_apic.handles(gsi)).ok_or(ApicError::NoIoApicForGsi(gsi))?;
+    let vector = controllers.allocate_vector(handler)?;
+
+    // Fixed delivery to a physical APIC ID. Without interrupt remapping the destination is 8 bits even in x2APIC mode.
+    let mut entry = vector as u64 | ((controllers.bsp_apic_id as u64 & 0xFF) << 56);
+    if trigger == Trigger::Level {
+        entry |= REDIRECTION_LEVEL;
+    }
+    if polarity == Polarity::ActiveLow {
+        entry |= REDIRECTION_ACTIVE_LOW;
+    }
+    controllers.io_apics[index].set_redirection(gsi, entry);
+
+    Ok(vector)
+}
+
+/// Routes legacy ISA IRQ `irq`, following the MADT's interrupt source overrides. ISA interrupts
+/// are edge triggered and active high unless an override says otherwise.
+#[allow(dead_code)]
+pub fn route_irq(irq: u8, handler: fn()) -> Result<u8, ApicError> {
+    let (gsi, flags) = match CONTROLLERS.lock().as_ref() {
+        Some(controllers) => match controllers.overrides.iter().find(|o| o.irq == irq) {
+            Some(o) => (o.gsi, o.flags),
+            None => (irq as u32, 0),
+        },
+        None => return Err(ApicError::NotInitialized),
+    };
+
+    let trigger = match flags & acpi::INTI_TRIGGER_MASK {
+        acpi::INTI_LEVEL => Trigger::Level,
+        _ => Trigger::Edge,
+    };
+    let polarity = match flags & acpi::INTI_POLARITY_MASK {
+        acpi::INTI_ACTIVE_LOW => Polarity::ActiveLow,
+        _ => Polarity::ActiveHigh,
+    };
+
+    route_gsi(gsi, trigger, polarity, handler)
+}
+
+/// Acknowledges `vector` to the local APIC. Spurious interrupts, including the legacy PIC's, must not be acknowledged.
+pub fn end_of_interrupt(vector: u8) {
+    if vector < FIRST_VECTOR || vector == SPURIOUS_VECTOR {
+        return;
+    }
+
+    lapic_write(LAPIC_EOI, 0);
+}
+
+/// Counts the local APIC timer down (divided by 16) while `wait` runs and returns how many ticks passed
+pub fn measure_timer(wait: impl FnOnce()) -> Result<u32, ApicError> {
+    if CONTROLLERS.lock().is_none() {
+        return Err(ApicError::NotInitialized);
+    }
+
+    lapic_write(LAPIC_LVT_TIMER, LVT_MASKED);
+    lapic_write(LAPIC_TIMER_DIVIDE, TIMER_DIVIDE_BY_16);
+    lapic_write(LAPIC_TIMER_INITIAL_COUNT, u32::MAX);
+    wait();
+    let remaining = lapic_read(LAPIC_TIMER_CURRENT_COUNT);
+    lapic_write(LAPIC_TIMER_INITIAL_COUNT, 0);
+
+    Ok(u32::MAX - remaining)
+}
+
+/// Fires `handler` on this CPU every `count` timer ticks (divided by 16, as in measure_timer) and returns its vector
+pub fn start_periodic_timer(count: u32, handler: fn()) -> Result<u8, ApicError> {
+    let vector = CONTROLLERS.lock().as_mut().ok_or(ApicError::NotInitialized)?.allocate_vector(handler)?;
+
+    lapic_write(LAPIC_TIMER_DIVIDE, TIMER_DIVIDE_BY_16);
+    lapic_write(LAPIC_LVT_TIMER, LVT_TIMER_PERIODIC | vector as u32);
+    lapic_write(LAPIC_TIMER_INITIAL_COUNT, count.max(1));
+
+    Ok(vector)
+}
\ No newline at end of file
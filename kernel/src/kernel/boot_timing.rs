@@ -0,0 +1,58 @@
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use boot_protocol::BootInfo;
+use spin::mutex::Mutex;
+
+use crate::kernel::cpu;
+use crate::kserialprint;
+
+struct Timeline {
+    tsc_frequency: u64, // Hz, 0 if the bootloader could not measure it
+    phases: Vec<(String, u64)>, // Phase name and the TSC when it finished
+}
+
+static TIMELINE: Mutex<Option<Timeline>> = Mutex::new(None);
+
+/// Starts the timeline with the bootloader's phases. `entry_tsc` is read first thing in `_start`,
+/// so the gap after the bootloader's last mark is the jump into the kernel.
+pub fn init(boot_info: &BootInfo, entry_tsc: u64) {
+    let mut phases: Vec<(String, u64)> = unsafe { boot_info.timestamps() }.iter()
+        .map(|timestamp| (timestamp.name().to_string(), timestamp.tsc))
+        .collect();
+    phases.push(("kernel entry".to_string(), entry_tsc));
+
+    *TIMELINE.lock() = Some(Timeline { tsc_frequency: boot_info.tsc_frequency, phases });
+}
+
+/// Records that the kernel phase `name` has just finished
+pub fn mark(name: &str) {
+    if let Some(timeline) = TIMELINE.lock().as_mut() {
+        timeline.phases.push((name.to_string(), cpu::rdtsc()));
+    }
+}
+
+fn format_duration(cycles: u64, tsc_frequency: u64) -> String {
+    if tsc_frequency == 0 {
+        return alloc::format!("{} cycles", cycles);
+    }
+
+    let micros = (cycles as u128 * 1_000_000 / tsc_frequency as u128) as u64;
+    alloc::format!("{}.{:03} ms", micros / 1000, micros % 1000)
+}
+
+/// Prints how long each phase took, from firmware reset to the last mark, to serial
+pub fn report() {
+    let guard = TIMELINE.lock();
+    let timeline = match guard.as_ref() {
+        Some(t) => t,
+        None => return,
+    };
+
+    kserialprint!("Boot time breakdown, TSC at {} kHz:\n", timeline.tsc_frequency / 1000);
+    let mut previous = 0;
+    for (name, tsc) in &timeline.phases {
+        kserialprint!("  {:<16} {:>14}\n", name, format_duration(tsc.saturating_sub(previous), timeline.tsc_frequency));
+        previous = *tsc;
+    }
+    kserialprint!("  {:<16} {:>14}\n", "total", format_duration(previous, timeline.tsc_frequency));
+}
\ No newline at end of file
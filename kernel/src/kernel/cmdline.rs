@@ -0,0 +1,25 @@
+use boot_protocol::BootInfo;
+use formats::cmdline::Cmdline;
+use spin::mutex::Mutex;
+
+static CMDLINE: Mutex<Option<Cmdline<'static>>> = Mutex::new(None);
+
+/// Keeps the command line the bootloader handed over. It lives in LOADER_DATA memory,
+/// which the frame allocator never hands out, so borrowing it for 'static is fine.
+pub fn init(boot_info: &'static BootInfo) {
+    *CMDLINE.lock() = Some(Cmdline::new(unsafe { boot_info.cmdline() }));
+}
+
+pub fn get() -> Cmdline<'static> {
+    CMDLINE.lock().unwrap_or(Cmdline::new(""))
+}
+
+/// True if the command line contains `name` as a bare word, e.g. `noahci`
+pub fn flag(name: &str) -> bool {
+    get().flag(name)
+}
+
+#[allow(dead_code)]
+pub fn value(name: &str) -> Option<&'static str> {
+    get().value(name)
+}
\ No newline at end of file
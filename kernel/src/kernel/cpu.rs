@@ -0,0 +1,142 @@
+use core::arch::asm;
+
+pub const IA32_EFER: u32 = 0xC0000080;
+
+pub fn read_cr3() -> u64 {
+    let value: u64;
+    unsafe {
+        asm!("mov {}, cr3", out(reg) value, options(nomem, nostack, preserves_flags));
+    }
+    value
+}
+
+/// # Safety
+/// `value` must point to a PML4 that maps the currently executing code and stack.
+pub unsafe fn write_cr3(value: u64) {
+    unsafe {
+        asm!("mov cr3, {}", in(reg) value, options(nostack, preserves_flags));
+    }
+}
+
+pub fn read_cr0() -> u64 {
+    let value: u64;
+    unsafe {
+        asm!("mov {}, cr0", out(reg) value, options(nomem, nostack, preserves_flags));
+    }
+    value
+}
+
+pub unsafe fn write_cr0(value: u64) {
+    unsafe {
+        asm!("mov cr0, {}", in(reg) value, options(nostack, preserves_flags));
+    }
+}
+
+pub fn rdmsr(msr: u32) -> u64 {
+    let (low, high): (u32, u32);
+    unsafe {
+        asm!("rdmsr", in("ecx") msr, out("eax") low, out("edx") high, options(nomem, nostack, preserves_flags));
+    }
+    ((high as u64) << 32) | low as u64
+}
+
+pub unsafe fn wrmsr(msr: u32, value: u64) {
+    unsafe {
+        asm!(
+            "wrmsr",
+            in("ecx") msr,
+            in("eax") value as u32,
+            in("edx") (value >> 32) as u32,
+            options(nostack, preserves_flags),
+        );
+    }
+}
+
+pub fn invlpg(address: usize) {
+    unsafe {
+        asm!("invlpg [{}]", in(reg) address, options(nostack, preserves_flags));
+    }
+}
+
+pub fn rdtsc() -> u64 {
+    let (low, high): (u32, u32);
+    unsafe {
+        asm!("rdtsc", out("eax") low, out("edx") high, options(nomem, nostack, preserves_flags));
+    }
+    ((high as u64) << 32) | low as u64
+}
+
+/// Stops this CPU for good. Interrupts are disabled first so nothing wakes it up again.
+pub fn halt() -> ! {
+    loop {
+        unsafe {
+            asm!("cli", "hlt", options(nomem, nostack));
+        }
+    }
+}
+
+/// RBP of the function this is inlined into, the head of its frame pointer chain
+#[inline(always)]
+pub fn frame_pointer() -> usize {
+    let value: usize;
+    unsafe {
+        asm!("mov {}, rbp", out(reg) value, options(nomem, nostack, preserves_flags));
+    }
+    value
+}
+
+pub fn read_cr2() -> u64 {
+    let value: u64;
+    unsafe {
+        asm!("mov {}, cr2", out(reg) value, options(nomem, nostack, preserves_flags));
+    }
+    value
+}
+
+pub fn read_cs() -> u16 {
+    let value: u16;
+    unsafe {
+        asm!("mov {:x}, cs", out(reg) value, options(nomem, nostack, preserves_flags));
+    }
+    value
+}
+
+pub fn enable_interrupts() {
+    unsafe {
+        asm!("sti", options(nomem, nostack));
+    }
+}
+
+pub fn disable_interrupts() {
+    unsafe {
+        asm!("cli", options(nomem, nostack));
+    }
+}
+
+pub fn interrupts_enabled() -> bool {
+    let rflags: u64;
+    unsafe {
+        asm!("pushfq", "pop {}", out(reg) rflags, options(nomem, preserves_flags));
+    }
+    rflags & (1 << 9) != 0
+}
+
+/// Runs `f` with interrupts off, so it can take locks that interrupt handlers also take
+pub fn without_interrupts<R>(f: impl FnOnce() -> R) -> R {
+    let enabled = interrupts_enabled();
+    if enabled {
+        disable_interrupts();
+    }
+    let result = f();
+    if enabled {
+        enable_interrupts();
+    }
+    result
+}
+
+/// Sleeps until the next interrupt
+pub fn wait_for_interrupt() {
+    unsafe {
+        asm!("hlt", options(nomem, nostack));
+    }
+}
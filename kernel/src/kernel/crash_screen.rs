@@ -0,0 +1,154 @@
+use boot_protocol::{BootInfo, FramebufferInfo};
+use core::fmt;
+use core::fmt::Write;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use formats::psf::PsfFont;
+
+use crate::drawing::fonts::draw_string;
+use crate::drawing::{Color, Framebuffer};
+use crate::kernel::{cpu, serial_io, symbols};
+use crate::MAIN_FONT;
+
+const MARGIN: usize = 8;
+const GLYPH_WIDTH: usize = 8;
+const MAX_FRAMES: usize = 32;
+
+// Address of the bootloader's FramebufferInfo. An atomic rather than a Mutex, since a fault may hit with any lock held.
+static FRAMEBUFFER_INFO: AtomicUsize = AtomicUsize::new(0);
+// The kernel stack, so backtraces only follow frame pointers that point into it
+static STACK_START: AtomicUsize = AtomicUsize::new(0);
+static STACK_END: AtomicUsize = AtomicUsize::new(0);
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
+pub fn init(boot_info: &BootInfo) {
+    FRAMEBUFFER_INFO.store(boot_info.framebuffer as usize, Ordering::Release);
+    STACK_START.store(boot_info.stack_virtual_base as usize, Ordering::Release);
+    STACK_END.store((boot_info.stack_virtual_base + boot_info.stack_size) as usize, Ordering::Release);
+}
+
+/// Reports a fatal error on COM1 and on a red screen. Both are written directly rather than
+/// through the event queue or the heap, since either of those may be what broke.
+pub struct CrashScreen {
+    framebuffer: Option<Framebuffer<'static>>,
+    font: Option<PsfFont<'static>>,
+    x: usize,
+    y: usize,
+}
+
+impl CrashScreen {
+    /// Clears the screen to red and starts writing at its top left corner
+    pub fn open() -> Self {
+        let info = FRAMEBUFFER_INFO.load(Ordering::Acquire) as *const FramebufferInfo;
+        let mut framebuffer = if info.is_null() {
+            None
+        } else {
+            Some(unsafe { Framebuffer::from_info(&*info) })
+        };
+        if let Some(fb) = framebuffer.as_mut() {
+            let pixel = fb.encode(Color::Red);
+            fb.buffer.fill(pixel);
+        }
+
+        serial_io::serial_write_str("\n");
+        Self { framebuffer, font: PsfFont::from_bytes(MAIN_FONT), x: MARGIN, y: MARGIN }
+    }
+
+    /// Draws one line of text, wrapping at the right edge. Whatever does not fit on the screen still goes out on serial.
+    fn draw_line(&mut self, mut text: &str) {
+        let (fb, font) = match (self.framebuffer.as_mut(), self.font.as_ref()) {
+            (Some(fb), Some(font)) => (fb, font),
+            _ => return,
+        };
+
+        while !text.is_empty() && self.y + font.glyph_height() <= fb.height {
+            let room = fb.width.saturating_sub(MARGIN + self.x) / GLYPH_WIDTH;
+            // Cut on a character boundary, but always take at least one character so a narrow screen still makes progress
+            let cut = match text.char_indices().map(|(i, c)| i + c.len_utf8()).take_while(|end| *end <= room).last() {
+                Some(cut) => cut,
+                None if self.x > MARGIN => {
+                    self.x = MARGIN;
+                    self.y += font.glyph_height();
+                    continue;
+                },
+                None => text.chars().next().map_or(text.len(), char::len_utf8),
+            };
+
+            let (now, rest) = text.split_at(cut);
+            draw_string(fb, font, now, self.x, self.y, Color::White);
+            self.x += now.len() * GLYPH_WIDTH;
+            text = rest;
+            if !text.is_empty() {
+                self.x = MARGIN;
+                self.y += font.glyph_height();
+            }
+        }
+    }
+
+    /// Follows the chain of saved frame pointers from `rbp`. The walk ends at the zero RBP the bootloader
+    /// enters the kernel with, or at the first frame pointer outside the kernel stack.
+    pub fn write_backtrace(&mut self, mut rbp: usize) -> fmt::Result {
+        let stack_start = STACK_START.load(Ordering::Acquire);
+        let stack_end = STACK_END.load(Ordering::Acquire);
+
+        writeln!(self, "Backtrace:")?;
+        for _ in 0..MAX_FRAMES {
+            if rbp < stack_start || rbp + 16 > stack_end || !rbp.is_multiple_of(8) {
+                break;
+            }
+
+            // The saved RBP of the caller, then the return address into it
+            let (next, return_address) = unsafe { (*(rbp as *const usize), *((rbp + 8) as *const usize)) };
+            if return_address == 0 {
+                break;
+            }
+            writeln!(self, "  {}", symbols::resolve(return_address))?;
+            rbp = next;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Write for CrashScreen {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        serial_io::serial_write_str(s);
+        for (index, line) in s.split('\n').enumerate() {
+            if index > 0 && let Some(font) = self.font.as_ref() {
+                self.x = MARGIN;
+                self.y += font.glyph_height();
+            }
+            self.draw_line(line);
+        }
+        Ok(())
+    }
+}
+
+/// Reports a kernel panic with its location and a backtrace, then halts.
+/// A panic while reporting one only gets a line on serial, since the report itself is what failed.
+pub fn panic(info: &PanicInfo) -> ! {
+    let rbp = cpu::frame_pointer();
+    if PANICKING.swap(true, Ordering::AcqRel) {
+        serial_io::serial_write_str("\nPanic while reporting a panic, halting\n");
+        cpu::halt();
+    }
+
+    let mut screen = CrashScreen::open();
+    let _ = writeln!(screen, "KERNEL PANIC: {}", info.message());
+    if let Some(location) = info.location() {
+        let _ = writeln!(screen, "at {}:{}:{}", location.file(), location.line(), location.column());
+    }
+    let _ = screen.write_backtrace(rbp);
+    let _ = writeln!(screen, "System halted");
+
+    cpu::halt()
+}
+
+/// Reports an unrecoverable error that is not a panic, such as running out of memory before the heap exists, and halts
+pub fn fatal(message: &str) -> ! {
+    let mut screen = CrashScreen::open();
+    let _ = writeln!(screen, "FATAL: {}", message);
+    let _ = writeln!(screen, "System halted");
+
+    cpu::halt()
+}
\ No newline at end of file
@@ -0,0 +1,89 @@
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use boot_protocol::BootInfo;
+use spin::mutex::Mutex;
+use uefi_raw::table::runtime::{ResetType, RuntimeServices, VariableAttributes, VariableVendor};
+use uefi_raw::{Guid, Status};
+
+use crate::kernel::paging;
+use crate::kprintln;
+
+static RUNTIME_SERVICES: Mutex<Option<&'static RuntimeServices>> = Mutex::new(None);
+
+fn runtime_services() -> Option<&'static RuntimeServices> {
+    *RUNTIME_SERVICES.lock()
+}
+
+/// Picks up the runtime services table. The bootloader switched the firmware to virtual mode
+/// with an identity map, and the kernel identity maps all runtime regions, so the table can be used as is.
+pub fn init(boot_info: &BootInfo) {
+    if boot_info.runtime_services != 0 {
+        *RUNTIME_SERVICES.lock() = Some(unsafe { &*(boot_info.runtime_services as *const RuntimeServices) });
+    } else {
+        kprintln!("No UEFI runtime services, reset and variables are unavailable");
+    }
+
+    if boot_info.smbios_entry != 0 {
+        let entry = paging::map_firmware(boot_info.smbios_entry as usize, 0x20) as *const u8;
+        let anchor = unsafe { core::slice::from_raw_parts(entry, 9) };
+        match anchor {
+            [b'_', b'S', b'M', b'3', b'_', _, _, major, minor] => {
+                kprintln!("SMBIOS {}.{} entry point at {:#x}", major, minor, boot_info.smbios_entry);
+            },
+            [b'_', b'S', b'M', b'_', _, _, major, minor, _] => {
+                kprintln!("SMBIOS {}.{} entry point at {:#x}", major, minor, boot_info.smbios_entry);
+            },
+            _ => {
+                kprintln!("SMBIOS entry point at {:#x} has no valid anchor", boot_info.smbios_entry);
+            }
+        }
+    }
+
+    let mut boot_current = [0u8; 2];
+    if let Ok(2) = get_variable("BootCurrent", &VariableVendor::GLOBAL_VARIABLE.0, &mut boot_current) {
+        kprintln!("Booted from Boot{:04X}", u16::from_le_bytes(boot_current));
+    }
+}
+
+/// Reads a UEFI variable into `buffer` and returns its size.
+/// BUFFER_TOO_SMALL means `buffer` could not hold it.
+pub fn get_variable(name: &str, vendor: &Guid, buffer: &mut [u8]) -> Result<usize, Status> {
+    let runtime_services = runtime_services().ok_or(Status::UNSUPPORTED)?;
+    let name: Vec<u16> = name.encode_utf16().chain(core::iter::once(0)).collect();
+
+    let mut attributes = VariableAttributes::empty();
+    let mut size = buffer.len();
+    let status = unsafe {
+        (runtime_services.get_variable)(name.as_ptr(), vendor, &mut attributes, &mut size, buffer.as_mut_ptr())
+    };
+
+    match status {
+        Status::SUCCESS => Ok(size),
+        error => Err(error),
+    }
+}
+
+/// Resets or powers off the machine through the firmware. Halts if the firmware cannot do it.
+pub fn reset(kind: ResetType) -> ! {
+    if let Some(runtime_services) = runtime_services() {
+        unsafe {
+            (runtime_services.reset_system)(kind, Status::SUCCESS, 0, core::ptr::null());
+        }
+    }
+
+    kprintln!("Firmware reset is unavailable, halting");
+    loop {
+        unsafe {
+            core::arch::asm!("cli; hlt", options(nomem, nostack));
+        }
+    }
+}
+
+pub fn shutdown() -> ! {
+    reset(ResetType::SHUTDOWN)
+}
+
+#[allow(dead_code)]
+pub fn reboot() -> ! {
+    reset(ResetType::COLD)
+}
\ No newline at end of file
@@ -0,0 +1,265 @@
+use core::ptr::write_bytes;
+
+use boot_protocol::BootInfo;
+use spin::mutex::Mutex;
+
+use crate::kprintln;
+
+pub const FRAME_SIZE: usize = 4096; // 4 KB
+const LOW_MEMORY_END: usize = 0x100000; // Leave the first MB alone
+const MAX_RESERVED_REGIONS: usize = 16;
+
+pub static FRAME_ALLOCATOR: Mutex<Option<FrameAllocator>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Copy)]
+pub struct FrameStats {
+    pub total_frames: usize,
+    pub free_frames: usize,
+    pub used_frames: usize,
+    pub reserved_frames: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ReservedRegion {
+    pub name: &'static str,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Bitmap allocator over every physical frame below the highest reclaimable address.
+/// A set bit means the frame is unavailable: in use, reserved or not RAM at all.
+pub struct FrameAllocator {
+    bitmap: &'static mut [u64],
+    frame_count: usize,
+    usable_frames: usize,
+    free_frames: usize,
+    reserved_frames: usize,
+    next_free: usize,
+    reserved: [Option<ReservedRegion>; MAX_RESERVED_REGIONS],
+}
+
+impl FrameAllocator {
+    fn is_set(&self, frame: usize) -> bool {
+        self.bitmap[frame / 64] & (1 << (frame % 64)) != 0
+    }
+
+    fn set(&mut self, frame: usize) {
+        self.bitmap[frame / 64] |= 1 << (frame % 64);
+    }
+
+    fn clear(&mut self, frame: usize) {
+        self.bitmap[frame / 64] &= !(1 << (frame % 64));
+    }
+
+    /// Marks the frames covering `start..end` as unavailable and remembers the region by name.
+    pub fn reserve_region(&mut self, name: &'static str, start: usize, end: usize) {
+        let first = start / FRAME_SIZE;
+        let last = end.div_ceil(FRAME_SIZE).min(self.frame_count);
+
+        for frame in first..last {
+            if !self.is_set(frame) {
+                self.set(frame);
+                self.free_frames -= 1;
+                self.reserved_frames += 1;
+            }
+        }
+
+        match self.reserved.iter_mut().find(|r| r.is_none()) {
+            Some(slot) => *slot = Some(ReservedRegion { name, start, end }),
+            None => {
+                kprintln!("Reserved region table full, not tracking {}", name);
+            }
+        }
+    }
+
+    pub fn reserved_regions(&self) -> impl Iterator<Item = &ReservedRegion> {
+        self.reserved.iter().flatten()
+    }
+
+    pub fn allocate_frame(&mut self) -> Option<usize> {
+        let first_word = self.next_free / 64;
+        for word_index in (first_word..self.bitmap.len()).chain(0..first_word) {
+            let word = self.bitmap[word_index];
+            if word == u64::MAX {
+                continue;
+            }
+
+            let frame = word_index * 64 + (!word).trailing_zeros() as usize;
+            if frame >= self.frame_count {
+                continue;
+            }
+
+            self.set(frame);
+            self.free_frames -= 1;
+            self.next_free = frame + 1;
+            return Some(frame * FRAME_SIZE);
+        }
+
+        None
+    }
+
+    /// Allocates `count` physically contiguous frames whose start is aligned to `align` bytes.
+    pub fn allocate_contiguous(&mut self, count: usize, align: usize) -> Option<usize> {
+        if count == 0 {
+            return None;
+        }
+
+        let step = (align / FRAME_SIZE).max(1);
+        let mut start = 0;
+        while start + count <= self.frame_count {
+            match (start..start + count).find(|&frame| self.is_set(frame)) {
+                Some(used) => {
+                    // Skip past the used frame, staying on the alignment grid
+                    start = (used + 1).div_ceil(step) * step;
+                }
+                None => {
+                    for frame in start..start + count {
+                        self.set(frame);
+                    }
+                    self.free_frames -= count;
+                    return Some(start * FRAME_SIZE);
+                }
+            }
+        }
+
+        None
+    }
+
+    pub fn free_frame(&mut self, address: usize) {
+        if !address.is_multiple_of(FRAME_SIZE) {
+            kprintln!("Tried to free unaligned frame {:#x}", address);
+            return;
+        }
+
+        let frame = address / FRAME_SIZE;
+        if frame >= self.frame_count || !self.is_set(frame) {
+            kprintln!("Tried to free frame {:#x} which is not allocated", address);
+            return;
+        }
+
+        if self.reserved_regions().any(|r| address >= r.start && address < r.end) {
+            kprintln!("Tried to free frame {:#x} inside a reserved region", address);
+            return;
+        }
+
+        self.clear(frame);
+        self.free_frames += 1;
+        if frame < self.next_free {
+            self.next_free = frame;
+        }
+    }
+
+    pub fn free_contiguous(&mut self, address: usize, count: usize) {
+        for i in 0..count {
+            self.free_frame(address + i * FRAME_SIZE);
+        }
+    }
+
+    pub fn stats(&self) -> FrameStats {
+        FrameStats {
+            total_frames: self.usable_frames,
+            free_frames: self.free_frames,
+            used_frames: self.usable_frames - self.free_frames - self.reserved_frames,
+            reserved_frames: self.reserved_frames,
+        }
+    }
+}
+
+/// Builds the frame allocator from the firmware memory map. Must run before anything allocates frames.
+/// Runs before the heap exists, so failures are reported through the return value instead of kprintln.
+pub fn init(boot_info: &BootInfo) -> bool {
+    let highest_address = unsafe { boot_info.memory_descriptors() }
+        .filter(|desc| desc.is_reclaimable())
+        .map(|desc| desc.phys_end() as usize)
+        .max()
+        .unwrap_or(0);
+    let frame_count = highest_address / FRAME_SIZE;
+    let bitmap_bytes = frame_count.div_ceil(64) * 8;
+
+    // The bitmap itself lives in the first free region big enough to hold it
+    let bitmap_region = unsafe { boot_info.memory_descriptors() }
+        .find(|desc| desc.is_usable()
+            && desc.phys_start as usize >= LOW_MEMORY_END
+            && (desc.page_count as usize) * FRAME_SIZE >= bitmap_bytes);
+    let bitmap_start = match bitmap_region {
+        Some(desc) => desc.phys_start as usize,
+        None => return false,
+    };
+
+    let bitmap = unsafe {
+        core::slice::from_raw_parts_mut(bitmap_start as *mut u64, bitmap_bytes / 8)
+    };
+    bitmap.fill(u64::MAX);
+
+    let mut allocator = FrameAllocator {
+        bitmap,
+        frame_count,
+        usable_frames: 0,
+        free_frames: 0,
+        reserved_frames: 0,
+        next_free: LOW_MEMORY_END / FRAME_SIZE,
+        reserved: [None; MAX_RESERVED_REGIONS],
+    };
+
+    for desc in unsafe { boot_info.memory_descriptors() }.filter(|desc| desc.is_reclaimable()) {
+        let first = (desc.phys_start as usize).max(LOW_MEMORY_END) / FRAME_SIZE;
+        let last = desc.phys_end() as usize / FRAME_SIZE;
+        for frame in first..last {
+            if allocator.is_set(frame) {
+                allocator.clear(frame);
+                allocator.usable_frames += 1;
+                allocator.free_frames += 1;
+            }
+        }
+    }
+
+    allocator.reserve_region("frame bitmap", bitmap_start, bitmap_start + bitmap_bytes);
+
+    let kernel_start = boot_info.kernel_physical_base as usize;
+    allocator.reserve_region("kernel image", kernel_start, kernel_start + boot_info.kernel_size as usize);
+
+    let framebuffer = unsafe { &*boot_info.framebuffer };
+    let framebuffer_start = framebuffer.base as usize;
+    allocator.reserve_region("framebuffer", framebuffer_start, framebuffer_start + framebuffer.size);
+
+    let stack_start = boot_info.stack_physical_base as usize;
+    allocator.reserve_region("kernel stack", stack_start, stack_start + boot_info.stack_size as usize);
+
+    *FRAME_ALLOCATOR.lock() = Some(allocator);
+    true
+}
+
+pub fn allocate_frame() -> Option<usize> {
+    FRAME_ALLOCATOR.lock().as_mut()?.allocate_frame()
+}
+
+pub fn allocate_contiguous(count: usize, align: usize) -> Option<usize> {
+    FRAME_ALLOCATOR.lock().as_mut()?.allocate_contiguous(count, align)
+}
+
+#[allow(dead_code)]
+pub fn free_frame(address: usize) {
+    if let Some(allocator) = FRAME_ALLOCATOR.lock().as_mut() {
+        allocator.free_frame(address);
+    }
+}
+
+#[allow(dead_code)]
+pub fn free_contiguous(address: usize, count: usize) {
+    if let Some(allocator) = FRAME_ALLOCATOR.lock().as_mut() {
+        allocator.free_contiguous(address, count);
+    }
+}
+
+pub fn stats() -> Option<FrameStats> {
+    FRAME_ALLOCATOR.lock().as_ref().map(|allocator| allocator.stats())
+}
+
+pub fn zero_page(pointer: *mut u8, count: Option<usize>) {
+    unsafe {
+        match count {
+            Some(c) => write_bytes(pointer, 0, c),
+            None => write_bytes(pointer, 0, FRAME_SIZE)
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use alloc::boxed::Box;
+use alloc::string::ToString;
+use core::arch::asm;
+use core::mem::size_of;
+use spin::mutex::Mutex;
+
+use crate::kernel::frame_allocator::{self, FRAME_SIZE};
+use crate::kernel::paging::{self, phys_to_virt};
+use crate::kprintln;
+
+// Selectors. User data comes before user code, the order SYSRET expects.
+pub const KERNEL_CODE_SELECTOR: u16 = 0x08;
+pub const KERNEL_DATA_SELECTOR: u16 = 0x10;
+#[allow(dead_code)]
+pub const USER_DATA_SELECTOR: u16 = 0x18 | 3;
+#[allow(dead_code)]
+pub const USER_CODE_SELECTOR: u16 = 0x20 | 3;
+pub const TSS_SELECTOR: u16 = 0x28;
+
+// Interrupt stack table slots, as the IDT refers to them (1-based, 0 means no stack switch)
+pub const DOUBLE_FAULT_IST: u8 = 1;
+pub const NMI_IST: u8 = 2;
+pub const MACHINE_CHECK_IST: u8 = 3;
+
+const IST_STACK_FRAMES: usize = 4; // 16 KiB each, plus an unmapped guard frame below
+
+const KERNEL_CODE: u64 = 0x00AF_9A00_0000_FFFF; // Present, ring 0, execute/read, long mode
+const KERNEL_DATA: u64 = 0x00CF_9200_0000_FFFF; // Present, ring 0, read/write
+const USER_DATA: u64 = 0x00CF_F200_0000_FFFF; // Present, ring 3, read/write
+const USER_CODE: u64 = 0x00AF_FA00_0000_FFFF; // Present, ring 3, execute/read, long mode
+const TSS_AVAILABLE: u64 = 0x89; // Present, 64-bit TSS (available)
+
+/// The 64-bit task state segment. Only the stack pointers matter in long mode.
+#[repr(C, packed)]
+struct TaskStateSegment {
+    reserved0: u32,
+    rsp: [u64; 3], // Stacks for privilege changes to rings 0-2
+    reserved1: u64,
+    ist: [u64; 7],
+    reserved2: u64,
+    reserved3: u16,
+    io_map_base: u16,
+}
+
+const _: () = assert!(size_of::<TaskStateSegment>() == 104);
+
+#[repr(C, align(16))]
+struct Gdt {
+    entries: [u64; 7], // The TSS descriptor takes the last two
+}
+
+#[repr(C, packed)]
+struct DescriptorPointer {
+    limit: u16,
+    base: u64,
+}
+
+// Tops of the IST stacks in the loaded TSS, None until init succeeds
+static IST_STACKS: Mutex<Option<[u64; 3]>> = Mutex::new(None);
+
+/// A CPU's descriptor tables. They are leaked, since the CPU keeps pointing at them.
+struct CpuTables {
+    gdt: Gdt,
+    tss: TaskStateSegment,
+}
+
+fn tss_descriptor(tss: &TaskStateSegment) -> [u64; 2] {
+    let base = tss as *const TaskStateSegment as u64;
+    let limit = (size_of::<TaskStateSegment>() - 1) as u64;
+
+    let low = (limit & 0xFFFF)
+        | ((base & 0xFF_FFFF) << 16)
+        | (TSS_AVAILABLE << 40)
+        | (((limit >> 16) & 0xF) << 48)
+        | (((base >> 24) & 0xFF) << 56);
+    [low, base >> 32]
+}
+
+/// Allocates an interrupt stack, with a spare frame below it for `unmap_ist_guards` to turn into a guard
+/// page, and returns its top
+fn allocate_ist_stack() -> Option<u64> {
+    let base = frame_allocator::allocate_contiguous(IST_STACK_FRAMES + 1, FRAME_SIZE)?;
+
+    Some((phys_to_virt(base) + (IST_STACK_FRAMES + 1) * FRAME_SIZE) as u64)
+}
+
+/// Loads the GDT and TSS and reloads every segment register
+unsafe fn load(tables: &'static CpuTables) {
+    let pointer = DescriptorPointer {
+        limit: (size_of::<Gdt>() - 1) as u16,
+        base: &tables.gdt as *const Gdt as u64,
+    };
+
+    unsafe {
+        asm!("lgdt [{}]", in(reg) &pointer, options(readonly, nostack, preserves_flags));
+
+        // CS can only be reloaded by a far transfer, so return to the next instruction through the new selector
+        asm!(
+            "push {code}",
+            "lea {tmp}, [rip + 2f]",
+            "push {tmp}",
+            "retfq",
+            "2:",
+            code = in(reg) KERNEL_CODE_SELECTOR as u64,
+            tmp = lateout(reg) _,
+            options(preserves_flags),
+        );
+
+        asm!(
+            "mov ds, {data:x}",
+            "mov es, {data:x}",
+            "mov ss, {data:x}",
+            "mov fs, {null:x}",
+            "mov gs, {null:x}",
+            data = in(reg) KERNEL_DATA_SELECTOR,
+            null = in(reg) 0u16,
+            options(nostack, preserves_flags),
+        );
+
+        asm!("ltr {:x}", in(reg) TSS_SELECTOR, options(nostack, preserves_flags));
+    }
+}
+
+/// Installs this CPU's GDT and TSS. Needs the heap and the frame allocator.
+/// Returns false if that failed and the firmware's GDT is still loaded, without a TSS.
+pub fn init() -> bool {
+    let mut tss = TaskStateSegment {
+        reserved0: 0,
+        rsp: [0; 3],
+        reserved1: 0,
+        ist: [0; 7],
+        reserved2: 0,
+        reserved3: 0,
+        // Past the end of the TSS, so there is no I/O permission bitmap
+        io_map_base: size_of::<TaskStateSegment>() as u16,
+    };
+
+    for slot in [DOUBLE_FAULT_IST, NMI_IST, MACHINE_CHECK_IST] {
+        match allocate_ist_stack() {
+            Some(top) => tss.ist[slot as usize - 1] = top,
+            None => {
+                kprintln!("Out of memory for interrupt stacks, keeping the firmware GDT");
+                return false;
+            }
+        }
+    }
+
+    let tables = Box::leak(Box::new(CpuTables {
+        gdt: Gdt { entries: [0, KERNEL_CODE, KERNEL_DATA, USER_DATA, USER_CODE, 0, 0] },
+        tss,
+    }));
+    let [tss_low, tss_high] = tss_descriptor(&tables.tss);
+    tables.gdt.entries[5] = tss_low;
+    tables.gdt.entries[6] = tss_high;
+
+    unsafe {
+        load(tables);
+    }
+
+    let ist = tables.tss.ist;
+    kprintln!("Loaded GDT at {:#x}, TSS with IST stacks at {:#x}, {:#x}, {:#x}",
+        &tables.gdt as *const Gdt as usize, ist[0], ist[1], ist[2]);
+    *IST_STACKS.lock() = Some([ist[0], ist[1], ist[2]]);
+    true
+}
+
+/// Unmaps the frame below each IST stack, so an overflow faults instead of running into other memory.
+/// `init` runs before the kernel page tables exist, so this is done once `paging::init` has switched to them.
+pub fn unmap_ist_guards() {
+    if let Some(stacks) = IST_STACKS.lock().as_ref() {
+        for top in stacks {
+            let guard = *top as usize - (IST_STACK_FRAMES + 1) * FRAME_SIZE;
+            let _ = paging::unmap(guard);
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,349 @@
+use alloc::boxed::Box;
+use core::arch::{asm, naked_asm};
+use core::fmt::Write;
+use core::mem::size_of;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::kernel::crash_screen::CrashScreen;
+use crate::kernel::{apic, cpu, gdt, symbols};
+use crate::kprintln;
+
+const IDT_ENTRIES: usize = 256;
+const EXCEPTION_COUNT: usize = 32;
+const IRQ_STUB_SIZE: usize = 16;
+const INTERRUPT_GATE: u8 = 0x8E; // Present, ring 0, 64-bit interrupt gate
+const PAGE_FAULT: u64 = 14;
+
+const EXCEPTION_NAMES: [&str; EXCEPTION_COUNT] = [
+    "Divide error",
+    "Debug",
+    "Non-maskable interrupt",
+    "Breakpoint",
+    "Overflow",
+    "Bound range exceeded",
+    "Invalid opcode",
+    "Device not available",
+    "Double fault",
+    "Coprocessor segment overrun",
+    "Invalid TSS",
+    "Segment not present",
+    "Stack-segment fault",
+    "General protection fault",
+    "Page fault",
+    "Reserved",
+    "x87 floating-point exception",
+    "Alignment check",
+    "Machine check",
+    "SIMD floating-point exception",
+    "Virtualization exception",
+    "Control protection exception",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Hypervisor injection exception",
+    "VMM communication exception",
+    "Security exception",
+    "Reserved",
+];
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct GateDescriptor {
+    offset_low: u16,
+    selector: u16,
+    ist: u8, // Interrupt stack table slot, 0 to stay on the current stack
+    type_attributes: u8,
+    offset_mid: u16,
+    offset_high: u32,
+    reserved: u32,
+}
+
+const _: () = assert!(size_of::<GateDescriptor>() == 16);
+
+#[repr(C, packed)]
+struct DescriptorPointer {
+    limit: u16,
+    base: u64,
+}
+
+/// What the exception stubs leave on the stack: the registers pushed by `exception_common`,
+/// the vector and error code pushed by the stub, then the frame the CPU pushed.
+#[repr(C)]
+pub struct ExceptionFrame {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+    pub vector: u64,
+    pub error_code: u64, // 0 for exceptions without one
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+impl GateDescriptor {
+    const MISSING: Self = Self {
+        offset_low: 0,
+        selector: 0,
+        ist: 0,
+        type_attributes: 0,
+        offset_mid: 0,
+        offset_high: 0,
+        reserved: 0,
+    };
+
+    fn interrupt(handler: usize, selector: u16, ist: u8) -> Self {
+        Self {
+            offset_low: handler as u16,
+            selector,
+            ist,
+            type_attributes: INTERRUPT_GATE,
+            offset_mid: (handler >> 16) as u16,
+            offset_high: (handler >> 32) as u32,
+            reserved: 0,
+        }
+    }
+}
+
+/// Saves the general purpose registers on top of what the stub pushed and hands the lot to `exception_handler`.
+/// The CPU frame, error code and vector make 7 quadwords and the registers 15 more, so RSP is still 16-byte aligned
+/// at the call, as the CPU aligns it before pushing its frame.
+#[unsafe(naked)]
+extern "C" fn exception_common() {
+    naked_asm!(
+        "push rax",
+        "push rbx",
+        "push rcx",
+        "push rdx",
+        "push rsi",
+        "push rdi",
+        "push rbp",
+        "push r8",
+        "push r9",
+        "push r10",
+        "push r11",
+        "push r12",
+        "push r13",
+        "push r14",
+        "push r15",
+        "mov rdi, rsp",
+        "cld",
+        "call {handler}",
+        "ud2",
+        handler = sym exception_handler,
+    );
+}
+
+// One entry stub per vector. Exceptions without an error code push a zero in its place, so every frame looks the same.
+macro_rules! exception_stubs {
+    ($($name:ident: $vector:literal $(, $error_code:ident)?;)*) => {
+        $(
+            #[unsafe(naked)]
+            extern "C" fn $name() {
+                naked_asm!(
+                    exception_stubs!(@dummy $($error_code)?),
+                    "push {vector}",
+                    "jmp {common}",
+                    vector = const $vector,
+                    common = sym exception_common,
+                );
+            }
+        )*
+
+        const EXCEPTION_STUBS: [extern "C" fn(); EXCEPTION_COUNT] = [$($name),*];
+    };
+    (@dummy error_code) => { "" };
+    (@dummy) => { "push 0" };
+}
+
+exception_stubs! {
+    exception_0: 0;
+    exception_1: 1;
+    exception_2: 2;
+    exception_3: 3;
+    exception_4: 4;
+    exception_5: 5;
+    exception_6: 6;
+    exception_7: 7;
+    exception_8: 8, error_code;
+    exception_9: 9;
+    exception_10: 10, error_code;
+    exception_11: 11, error_code;
+    exception_12: 12, error_code;
+    exception_13: 13, error_code;
+    exception_14: 14, error_code;
+    exception_15: 15;
+    exception_16: 16;
+    exception_17: 17, error_code;
+    exception_18: 18;
+    exception_19: 19;
+    exception_20: 20;
+    exception_21: 21, error_code;
+    exception_22: 22;
+    exception_23: 23;
+    exception_24: 24;
+    exception_25: 25;
+    exception_26: 26;
+    exception_27: 27;
+    exception_28: 28;
+    exception_29: 29, error_code;
+    exception_30: 30, error_code;
+    exception_31: 31;
+}
+
+fn report(screen: &mut CrashScreen, frame: &ExceptionFrame) -> core::fmt::Result {
+    let name = EXCEPTION_NAMES.get(frame.vector as usize).unwrap_or(&"Unknown");
+    writeln!(screen, "CPU EXCEPTION {}: {}, error code {:#x}", frame.vector, name, frame.error_code)?;
+    writeln!(screen, "RIP {}", symbols::resolve(frame.rip as usize))?;
+    if frame.vector == PAGE_FAULT {
+        writeln!(screen, "CR2 {:#018x}", cpu::read_cr2())?;
+    }
+    writeln!(screen, "CS  {:#06x} SS  {:#06x} RFLAGS {:#018x}", frame.cs, frame.ss, frame.rflags)?;
+
+    let registers = [
+        ("RAX", frame.rax), ("RBX", frame.rbx), ("RCX", frame.rcx), ("RDX", frame.rdx),
+        ("RSI", frame.rsi), ("RDI", frame.rdi), ("RBP", frame.rbp), ("RSP", frame.rsp),
+        ("R8 ", frame.r8), ("R9 ", frame.r9), ("R10", frame.r10), ("R11", frame.r11),
+        ("R12", frame.r12), ("R13", frame.r13), ("R14", frame.r14), ("R15", frame.r15),
+    ];
+    for row in registers.chunks(4) {
+        for (name, value) in row {
+            write!(screen, "{} {:#018x}  ", name, value)?;
+        }
+        writeln!(screen)?;
+    }
+
+    screen.write_backtrace(frame.rbp as usize)
+}
+
+extern "C" fn exception_handler(frame: &ExceptionFrame) -> ! {
+    let mut screen = CrashScreen::open();
+    let _ = report(&mut screen, frame);
+    let _ = writeln!(screen, "System halted");
+
+    cpu::halt()
+}
+
+// Device interrupt handlers by vector, as `fn()` addresses or 0. Atomics rather than a Mutex,
+// so an interrupt never spins on a lock held by the code it interrupted.
+static IRQ_HANDLERS: [AtomicUsize; IDT_ENTRIES] = [const { AtomicUsize::new(0) }; IDT_ENTRIES];
+
+/// Entry stubs for every vector after the exceptions, IRQ_STUB_SIZE bytes apart.
+/// Each pushes its vector and jumps to `irq_common`. The push is spelled out with a 32-bit immediate,
+/// since the short form would sign-extend vectors above 127.
+#[unsafe(naked)]
+extern "C" fn irq_stubs() {
+    naked_asm!(
+        ".set irq_stub_vector, {first}",
+        ".rept {count}",
+        ".balign {size}",
+        ".byte 0x68",
+        ".long irq_stub_vector",
+        "jmp {common}",
+        ".set irq_stub_vector, irq_stub_vector + 1",
+        ".endr",
+        first = const EXCEPTION_COUNT,
+        count = const IDT_ENTRIES - EXCEPTION_COUNT,
+        size = const IRQ_STUB_SIZE,
+        common = sym irq_common,
+    );
+}
+
+/// Saves the registers a Rust function may clobber, runs the handler and returns to the interrupted code.
+/// The CPU frame and the vector are 6 quadwords and the registers 9, so one more keeps RSP 16-byte aligned at the call.
+#[unsafe(naked)]
+extern "C" fn irq_common() {
+    naked_asm!(
+        "push rax",
+        "push rcx",
+        "push rdx",
+        "push rsi",
+        "push rdi",
+        "push r8",
+        "push r9",
+        "push r10",
+        "push r11",
+        "mov rdi, [rsp + 72]",
+        "cld",
+        "sub rsp, 8",
+        "call {dispatch}",
+        "add rsp, 8",
+        "pop r11",
+        "pop r10",
+        "pop r9",
+        "pop r8",
+        "pop rdi",
+        "pop rsi",
+        "pop rdx",
+        "pop rcx",
+        "pop rax",
+        "add rsp, 8",
+        "iretq",
+        dispatch = sym irq_dispatch,
+    );
+}
+
+extern "C" fn irq_dispatch(vector: u64) {
+    let handler = IRQ_HANDLERS[vector as usize].load(Ordering::Acquire);
+    if handler != 0 {
+        let handler: fn() = unsafe { core::mem::transmute(handler) };
+        handler();
+    }
+
+    apic::end_of_interrupt(vector as u8);
+}
+
+/// Runs `handler` with interrupts disabled whenever `vector` fires. The interrupt is acknowledged afterwards.
+pub fn set_irq_handler(vector: u8, handler: fn()) {
+    IRQ_HANDLERS[vector as usize].store(handler as *const () as usize, Ordering::Release);
+}
+
+/// Installs handlers for all 32 CPU exceptions, and stubs for the other vectors that call whatever
+/// `set_irq_handler` installed. Every exception is fatal for now: the handler reports it and halts.
+/// Needs the heap. `gdt_loaded` is what `gdt::init` returned: without the kernel GDT the gates use
+/// the firmware's code selector and no IST stacks.
+pub fn init(gdt_loaded: bool) {
+    let selector = if gdt_loaded { gdt::KERNEL_CODE_SELECTOR } else { cpu::read_cs() };
+    let idt = Box::leak(Box::new([GateDescriptor::MISSING; IDT_ENTRIES]));
+    for (vector, stub) in EXCEPTION_STUBS.iter().enumerate() {
+        let ist = match vector {
+            2 if gdt_loaded => gdt::NMI_IST,
+            8 if gdt_loaded => gdt::DOUBLE_FAULT_IST,
+            18 if gdt_loaded => gdt::MACHINE_CHECK_IST,
+            _ => 0,
+        };
+        idt[vector] = GateDescriptor::interrupt(*stub as *const () as usize, selector, ist);
+    }
+
+    // The .balign in irq_stubs also aligns the function itself, so the stubs start right at it
+    let irq_stubs = irq_stubs as *const () as usize;
+    for (index, gate) in idt[EXCEPTION_COUNT..].iter_mut().enumerate() {
+        *gate = GateDescriptor::interrupt(irq_stubs + index * IRQ_STUB_SIZE, selector, 0);
+    }
+
+    let pointer = DescriptorPointer {
+        limit: (size_of::<[GateDescriptor; IDT_ENTRIES]>() - 1) as u16,
+        base: idt.as_ptr() as u64,
+    };
+    unsafe {
+        asm!("lidt [{}]", in(reg) &pointer, options(readonly, nostack, preserves_flags));
+    }
+
+    kprintln!("Loaded IDT at {:#x}", idt.as_ptr() as usize);
+}
\ No newline at end of file
@@ -0,0 +1,42 @@
+use alloc::string::ToString;
+use boot_protocol::BootInfo;
+use formats::tar::TarArchive;
+use spin::mutex::Mutex;
+
+use crate::kprintln;
+
+static INITRD: Mutex<Option<TarArchive<'static>>> = Mutex::new(None);
+
+/// Picks up the ramdisk the bootloader loaded. It sits in LOADER_DATA pages, which the
+/// frame allocator never hands out, so the archive can be borrowed for 'static.
+pub fn init(boot_info: &'static BootInfo) {
+    let data = match unsafe { boot_info.initrd() } {
+        Some(data) => data,
+        None => {
+            kprintln!("No initrd");
+            return;
+        }
+    };
+
+    kprintln!("Initrd: {} bytes at {:#x}", data.len(), data.as_ptr() as usize);
+    let archive = TarArchive::new(data);
+    for entry in archive.entries() {
+        match entry {
+            Ok(entry) => {
+                kprintln!("  {} ({} bytes)", entry.path(), entry.data.len());
+            },
+            Err(e) => {
+                kprintln!("Initrd is corrupt: {:?}", e);
+                break;
+            }
+        }
+    }
+
+    *INITRD.lock() = Some(archive);
+}
+
+/// Looks up a regular file in the initrd by its path inside the archive.
+#[allow(dead_code)]
+pub fn find(path: &str) -> Option<&'static [u8]> {
+    INITRD.lock().as_ref()?.find(path)
+}
\ No newline at end of file
@@ -2,14 +2,29 @@ pub mod string_api;
 pub mod prelude;
 pub mod pci;
 pub mod ahci;
-pub mod page_heap;
+pub mod frame_allocator;
+pub mod paging;
+pub mod cpu;
+pub mod cmdline;
+pub mod initrd;
+pub mod firmware;
+pub mod acpi;
+pub mod symbols;
+pub mod boot_timing;
+pub mod gdt;
+pub mod idt;
+pub mod crash_screen;
+pub mod pic;
+pub mod apic;
+pub mod timer;
 pub mod serial_io;
 
 use alloc::vec::Vec;
-use alloc::{boxed::Box, string::String};
+use alloc::string::String;
 use alloc::collections::BTreeMap;
 use crate::kernel::string_api::{ONE_LINE_LENGTH, X_OFFSET_SHELL};
-use crate::{drawing::{fonts::{draw_string, PsfFont}, Color}, kernel::string_api::{Shell, Y_OFFSET_SHELL}, FramebufferInfo, MAIN_FONT};
+use formats::psf::PsfFont;
+use crate::{drawing::{fonts::draw_string, Color, Framebuffer}, kernel::string_api::{Shell, Y_OFFSET_SHELL}, MAIN_FONT};
 
 #[allow(dead_code)]
 pub enum EventType {
@@ -69,11 +84,11 @@ impl EventManager {
 pub struct Kernel<'a> {
     pub shell: Shell,
     pub fonts: BTreeMap<&'a str, PsfFont<'a>>,
-    pub framebuffer: Box<FramebufferInfo<'a>>,
+    pub framebuffer: Framebuffer<'a>,
 }
 
 impl<'a> Kernel<'a> {
-    pub fn start(framebuffer: Box<FramebufferInfo<'a>>, shell: Shell) -> Self {
+    pub fn start(framebuffer: Framebuffer<'a>, shell: Shell) -> Self {
         let psf_header = PsfFont::from_bytes(MAIN_FONT).unwrap();
         let mut fonts = BTreeMap::new();
         fonts.insert("main font", psf_header);
@@ -0,0 +1,390 @@
+use boot_protocol::{BootInfo, MemoryDescriptor};
+use spin::mutex::Mutex;
+
+use crate::kernel::cpu;
+use crate::kernel::frame_allocator::{self, FRAME_SIZE};
+use crate::kprintln;
+
+pub const PAGE_SIZE: usize = FRAME_SIZE;
+
+// Page table entry bits
+pub const PRESENT: u64 = 1 << 0;
+pub const WRITABLE: u64 = 1 << 1;
+pub const WRITE_THROUGH: u64 = 1 << 3;
+pub const CACHE_DISABLE: u64 = 1 << 4;
+pub const HUGE_PAGE: u64 = 1 << 7;
+pub const NO_EXECUTE: u64 = 1 << 63;
+
+pub const KERNEL_CODE: u64 = PRESENT;
+pub const KERNEL_RODATA: u64 = PRESENT | NO_EXECUTE;
+pub const KERNEL_DATA: u64 = PRESENT | WRITABLE | NO_EXECUTE;
+pub const MMIO: u64 = PRESENT | WRITABLE | WRITE_THROUGH | CACHE_DISABLE | NO_EXECUTE;
+
+const ADDRESS_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+const ENTRIES_PER_TABLE: usize = 512;
+const HUGE_PAGE_SIZE: usize = 0x200000; // 2 MiB
+const EFER_NXE: u64 = 1 << 11;
+const CR0_WP: u64 = 1 << 16;
+
+pub static KERNEL_SPACE: Mutex<Option<AddressSpace>> = Mutex::new(None);
+
+unsafe extern "C" {
+    static __text_start: u8;
+    static __text_end: u8;
+    static __rodata_start: u8;
+    static __rodata_end: u8;
+    static __data_start: u8;
+    static __data_end: u8;
+    static __bss_start: u8;
+    static __bss_end: u8;
+}
+
+#[derive(Debug, PartialEq)]
+pub enum MapError {
+    AlreadyMapped,
+    HugePageInTheWay,
+    Unaligned,
+    NotMapped,
+    NotInitialized, // paging::init has not built the kernel's address space
+    OutOfMemory,
+}
+
+#[repr(C, align(4096))]
+struct PageTable {
+    entries: [u64; ENTRIES_PER_TABLE],
+}
+
+/// All physical memory is identity mapped, so a physical address can be dereferenced as is.
+pub fn phys_to_virt(phys: usize) -> usize {
+    phys
+}
+
+fn table_indices(virt: usize) -> [usize; 4] {
+    [
+        (virt >> 39) & 0x1FF,
+        (virt >> 30) & 0x1FF,
+        (virt >> 21) & 0x1FF,
+        (virt >> 12) & 0x1FF,
+    ]
+}
+
+fn table_at(phys: usize) -> &'static mut PageTable {
+    unsafe { &mut *(phys_to_virt(phys) as *mut PageTable) }
+}
+
+/// A 4-level page table hierarchy rooted at a PML4.
+pub struct AddressSpace {
+    pml4: usize,
+}
+
+impl AddressSpace {
+    pub fn new() -> Option<Self> {
+        let pml4 = frame_allocator::allocate_frame()?;
+        frame_allocator::zero_page(phys_to_virt(pml4) as *mut u8, None);
+        Some(Self { pml4 })
+    }
+
+    /// Wraps the hierarchy CR3 currently points to.
+    pub fn active() -> Self {
+        Self { pml4: (cpu::read_cr3() & ADDRESS_MASK) as usize }
+    }
+
+    pub fn pml4_address(&self) -> usize {
+        self.pml4
+    }
+
+    fn next_table(entry: &mut u64) -> Result<usize, MapError> {
+        if *entry & PRESENT == 0 {
+            let frame = frame_allocator::allocate_frame().ok_or(MapError::OutOfMemory)?;
+            frame_allocator::zero_page(phys_to_virt(frame) as *mut u8, None);
+            // Permissions are enforced at the leaf, so intermediate levels stay permissive
+            *entry = frame as u64 | PRESENT | WRITABLE;
+        } else if *entry & HUGE_PAGE != 0 {
+            return Err(MapError::HugePageInTheWay);
+        }
+
+        Ok((*entry & ADDRESS_MASK) as usize)
+    }
+
+    pub fn map(&mut self, virt: usize, phys: usize, flags: u64) -> Result<(), MapError> {
+        if !virt.is_multiple_of(PAGE_SIZE) || !phys.is_multiple_of(PAGE_SIZE) {
+            return Err(MapError::Unaligned);
+        }
+
+        let indices = table_indices(virt);
+        let mut table = self.pml4;
+        for index in &indices[..3] {
+            table = Self::next_table(&mut table_at(table).entries[*index])?;
+        }
+
+        let entry = &mut table_at(table).entries[indices[3]];
+        if *entry & PRESENT != 0 {
+            return Err(MapError::AlreadyMapped);
+        }
+
+        *entry = phys as u64 | flags | PRESENT;
+        Ok(())
+    }
+
+    /// Maps one 2 MiB page. Fails with AlreadyMapped if anything is mapped in that 2 MiB already.
+    pub fn map_huge(&mut self, virt: usize, phys: usize, flags: u64) -> Result<(), MapError> {
+        if !virt.is_multiple_of(HUGE_PAGE_SIZE) || !phys.is_multiple_of(HUGE_PAGE_SIZE) {
+            return Err(MapError::Unaligned);
+        }
+
+        let indices = table_indices(virt);
+        let mut table = self.pml4;
+        for index in &indices[..2] {
+            table = Self::next_table(&mut table_at(table).entries[*index])?;
+        }
+
+        let entry = &mut table_at(table).entries[indices[2]];
+        if *entry & PRESENT != 0 {
+            return Err(MapError::AlreadyMapped);
+        }
+
+        *entry = phys as u64 | flags | PRESENT | HUGE_PAGE;
+        Ok(())
+    }
+
+    /// Maps `size` bytes starting at `virt` to `phys`, skipping pages that are already mapped.
+    /// Uses 2 MiB pages where both addresses are aligned and nothing is mapped in that 2 MiB yet.
+    pub fn map_range(&mut self, virt: usize, phys: usize, size: usize, flags: u64) -> Result<(), MapError> {
+        let offset = virt % PAGE_SIZE;
+        let pages = (size + offset).div_ceil(PAGE_SIZE);
+        let virt = virt - offset;
+        let phys = phys - (phys % PAGE_SIZE);
+        let pages_per_huge_page = HUGE_PAGE_SIZE / PAGE_SIZE;
+
+        let mut page = 0;
+        while page < pages {
+            let (page_virt, page_phys) = (virt + page * PAGE_SIZE, phys + page * PAGE_SIZE);
+            if pages - page >= pages_per_huge_page
+                && page_virt.is_multiple_of(HUGE_PAGE_SIZE)
+                && page_phys.is_multiple_of(HUGE_PAGE_SIZE)
+            {
+                match self.map_huge(page_virt, page_phys, flags) {
+                    Ok(()) => {
+                        page += pages_per_huge_page;
+                        continue;
+                    },
+                    Err(MapError::AlreadyMapped) => {}, // Fill in whatever 4 KiB pages are missing
+                    Err(e) => return Err(e),
+                }
+            }
+
+            match self.map(page_virt, page_phys, flags) {
+                Ok(()) | Err(MapError::AlreadyMapped) | Err(MapError::HugePageInTheWay) => {},
+                Err(e) => return Err(e),
+            }
+            page += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the 2 MiB page `entry` points to with a table of 4 KiB pages mapping the same memory.
+    fn split_huge_page(entry: &mut u64) -> Option<usize> {
+        let frame = frame_allocator::allocate_frame()?;
+        let base = *entry & ADDRESS_MASK & !(HUGE_PAGE_SIZE as u64 - 1);
+        let flags = *entry & !ADDRESS_MASK & !HUGE_PAGE;
+
+        let table = table_at(frame);
+        for (index, page) in table.entries.iter_mut().enumerate() {
+            *page = (base + (index * PAGE_SIZE) as u64) | flags;
+        }
+        *entry = frame as u64 | PRESENT | WRITABLE;
+        Some(frame)
+    }
+
+    /// Removes the 4 KiB mapping for `virt` and returns the physical frame it pointed to.
+    /// A 2 MiB page around `virt` is split first, so the rest of it stays mapped.
+    pub fn unmap(&mut self, virt: usize) -> Option<usize> {
+        let indices = table_indices(virt);
+        let mut table = self.pml4;
+        for (level, index) in indices[..3].iter().enumerate() {
+            let entry = &mut table_at(table).entries[*index];
+            if *entry & PRESENT == 0 {
+                return None;
+            }
+            table = if *entry & HUGE_PAGE == 0 {
+                (*entry & ADDRESS_MASK) as usize
+            } else if level == 2 {
+                Self::split_huge_page(entry)?
+            } else {
+                return None; // 1 GiB pages are never created here
+            };
+        }
+
+        let entry = &mut table_at(table).entries[indices[3]];
+        if *entry & PRESENT == 0 {
+            return None;
+        }
+
+        let phys = (*entry & ADDRESS_MASK) as usize;
+        *entry = 0;
+        if self.pml4 == (cpu::read_cr3() & ADDRESS_MASK) as usize {
+            cpu::invlpg(virt);
+        }
+
+        Some(phys)
+    }
+
+    pub fn translate(&self, virt: usize) -> Option<usize> {
+        let indices = table_indices(virt);
+        let mut table = self.pml4;
+
+        for (level, index) in indices.iter().enumerate() {
+            let entry = table_at(table).entries[*index];
+            if entry & PRESENT == 0 {
+                return None;
+            }
+
+            let address = (entry & ADDRESS_MASK) as usize;
+            // 1 GiB pages at the PDPT level, 2 MiB pages at the PD level
+            if entry & HUGE_PAGE != 0 && (level == 1 || level == 2) {
+                let page_size = if level == 1 { 1 << 30 } else { 1 << 21 };
+                let base = address & !(page_size - 1);
+                return Some(base + (virt & (page_size - 1)));
+            }
+
+            table = address;
+        }
+
+        Some(table + (virt % PAGE_SIZE))
+    }
+}
+
+fn memory_flags(desc: &MemoryDescriptor) -> Option<u64> {
+    match desc.ty {
+        boot_protocol::RESERVED | boot_protocol::UNUSABLE | boot_protocol::MMIO_PORT_SPACE => None,
+        boot_protocol::MMIO => Some(MMIO),
+        boot_protocol::RUNTIME_SERVICES_CODE => Some(PRESENT | WRITABLE),
+        _ => Some(KERNEL_DATA),
+    }
+}
+
+fn kernel_sections() -> [(usize, usize, u64); 4] {
+    unsafe {
+        [
+            (&__text_start as *const u8 as usize, &__text_end as *const u8 as usize, KERNEL_CODE),
+            (&__rodata_start as *const u8 as usize, &__rodata_end as *const u8 as usize, KERNEL_RODATA),
+            (&__data_start as *const u8 as usize, &__data_end as *const u8 as usize, KERNEL_DATA),
+            (&__bss_start as *const u8 as usize, &__bss_end as *const u8 as usize, KERNEL_DATA),
+        ]
+    }
+}
+
+fn build_kernel_space(boot_info: &BootInfo) -> Result<AddressSpace, MapError> {
+    // The bootloader's tables already map the higher-half kernel, so they tell us where each page really lives
+    let boot_tables = AddressSpace::active();
+    let mut space = AddressSpace::new().ok_or(MapError::OutOfMemory)?;
+
+    // Kernel sections first, so the identity map of RAM below does not widen their permissions
+    for (start, end, flags) in kernel_sections() {
+        let mut page = start & !(PAGE_SIZE - 1);
+        while page < end {
+            let phys = match boot_tables.translate(page) {
+                Some(p) => p,
+                None => {
+                    kprintln!("Kernel page {:#x} is not mapped by the bootloader", page);
+                    return Err(MapError::NotMapped);
+                }
+            };
+            match space.map(page, phys, flags) {
+                Ok(()) | Err(MapError::AlreadyMapped) => {},
+                Err(e) => return Err(e),
+            }
+            page += PAGE_SIZE;
+        }
+    }
+
+    // The stack lives above the image with a guard page below it, which stays unmapped here too
+    let stack_base = boot_info.stack_virtual_base as usize;
+    let stack_size = boot_info.stack_size as usize;
+    space.map_range(stack_base, boot_info.stack_physical_base as usize, stack_size, KERNEL_DATA)?;
+
+    // map_range uses 2 MiB pages for the aligned parts, which keeps the tables for large RAM ranges small
+    for desc in unsafe { boot_info.memory_descriptors() } {
+        let flags = match memory_flags(&desc) {
+            Some(f) => f,
+            None => continue,
+        };
+
+        // Page zero stays unmapped so null pointer dereferences fault
+        let start = (desc.phys_start as usize).max(PAGE_SIZE);
+        let end = desc.phys_end() as usize;
+        if start < end {
+            space.map_range(start, start, end - start, flags)?;
+        }
+    }
+
+    let framebuffer = unsafe { &*boot_info.framebuffer };
+    let framebuffer_start = framebuffer.base as usize;
+    space.map_range(framebuffer_start, framebuffer_start, framebuffer.size, MMIO)?;
+
+    Ok(space)
+}
+
+/// Builds the kernel's own page tables and switches CR3 to them.
+/// On failure the kernel keeps running on the bootloader's tables.
+pub fn init(boot_info: &BootInfo) {
+    unsafe {
+        cpu::wrmsr(cpu::IA32_EFER, cpu::rdmsr(cpu::IA32_EFER) | EFER_NXE);
+        cpu::write_cr0(cpu::read_cr0() | CR0_WP);
+    }
+
+    let space = match build_kernel_space(boot_info) {
+        Ok(s) => s,
+        Err(e) => {
+            kprintln!("Failed to build kernel page tables: {:?}. Staying on bootloader page tables.", e);
+            return;
+        }
+    };
+
+    unsafe {
+        cpu::write_cr3(space.pml4_address() as u64);
+    }
+    kprintln!("Switched to kernel page tables, PML4 at {:#x}", space.pml4_address());
+
+    *KERNEL_SPACE.lock() = Some(space);
+}
+
+#[allow(dead_code)]
+pub fn map(virt: usize, phys: usize, flags: u64) -> Result<(), MapError> {
+    match KERNEL_SPACE.lock().as_mut() {
+        Some(space) => space.map(virt, phys, flags),
+        None => Err(MapError::NotInitialized),
+    }
+}
+
+#[allow(dead_code)]
+pub fn unmap(virt: usize) -> Option<usize> {
+    KERNEL_SPACE.lock().as_mut()?.unmap(virt)
+}
+
+#[allow(dead_code)]
+pub fn translate(virt: usize) -> Option<usize> {
+    match KERNEL_SPACE.lock().as_ref() {
+        Some(space) => space.translate(virt),
+        None => AddressSpace::active().translate(virt),
+    }
+}
+
+/// Identity maps a device's register window uncached and returns the address to access it through.
+pub fn map_mmio(phys: usize, size: usize) -> usize {
+    if let Some(space) = KERNEL_SPACE.lock().as_mut() && let Err(e) = space.map_range(phys, phys, size, MMIO) {
+        kprintln!("Failed to map MMIO at {:#x}: {:?}", phys, e);
+    }
+
+    phys_to_virt(phys)
+}
+
+/// Maps read-only firmware data, such as ACPI tables, that may sit in memory the boot-time map skipped.
+pub fn map_firmware(phys: usize, size: usize) -> usize {
+    if let Some(space) = KERNEL_SPACE.lock().as_mut() && let Err(e) = space.map_range(phys, phys, size, KERNEL_RODATA) {
+        kprintln!("Failed to map firmware data at {:#x}: {:?}", phys, e);
+    }
+
+    phys_to_virt(phys)
+}
\ No newline at end of file
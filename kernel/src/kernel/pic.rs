@@ -0,0 +1,46 @@
+use crate::kernel::serial_io::outb;
+
+const MASTER_COMMAND: u16 = 0x20;
+const MASTER_DATA: u16 = 0x21;
+const SLAVE_COMMAND: u16 = 0xA0;
+const SLAVE_DATA: u16 = 0xA1;
+
+const ICW1_INIT: u8 = 0x11; // Initialize, ICW4 follows
+const ICW4_8086: u8 = 0x01;
+
+// IRQs 0-15 land on 0x20-0x2F, clear of the CPU exceptions. Only spurious IRQs 7 and 15 should ever arrive.
+pub const VECTOR_BASE: u8 = 0x20;
+pub const VECTOR_END: u8 = VECTOR_BASE + 16;
+
+/// Gives the 8259 PIC pair a few cycles to take each initialization word
+fn io_wait() {
+    unsafe {
+        outb(0x80, 0);
+    }
+}
+
+/// Moves the legacy PICs' vectors out of the exception range and masks every IRQ,
+/// so all device interrupts go through the I/O APIC instead.
+pub fn remap_and_mask() {
+    unsafe {
+        outb(MASTER_COMMAND, ICW1_INIT);
+        io_wait();
+        outb(SLAVE_COMMAND, ICW1_INIT);
+        io_wait();
+        outb(MASTER_DATA, VECTOR_BASE);
+        io_wait();
+        outb(SLAVE_DATA, VECTOR_BASE + 8);
+        io_wait();
+        outb(MASTER_DATA, 1 << 2); // The slave hangs off IRQ 2
+        io_wait();
+        outb(SLAVE_DATA, 2); // Cascade identity
+        io_wait();
+        outb(MASTER_DATA, ICW4_8086);
+        io_wait();
+        outb(SLAVE_DATA, ICW4_8086);
+        io_wait();
+
+        outb(MASTER_DATA, 0xFF);
+        outb(SLAVE_DATA, 0xFF);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,70 @@
+use alloc::string::ToString;
+use boot_protocol::BootInfo;
+use core::fmt;
+use formats::elf::SymbolTable;
+use spin::mutex::Mutex;
+
+use crate::kprintln;
+
+struct KernelSymbols {
+    table: SymbolTable<'static>,
+    bias: usize, // Runtime address minus link-time address, the KASLR slide
+}
+
+static SYMBOLS: Mutex<Option<KernelSymbols>> = Mutex::new(None);
+
+/// Picks up the symbol table the bootloader copied into LOADER_DATA pages. The slide is
+/// worked out from where `_start` actually runs, so no extra handoff field is needed.
+pub fn init(boot_info: &'static BootInfo) {
+    let (symtab, strtab) = match unsafe { boot_info.symbols() } {
+        Some(tables) => tables,
+        None => {
+            kprintln!("No kernel symbols, addresses will not be symbolized");
+            return;
+        }
+    };
+
+    let table = SymbolTable::new(symtab, strtab);
+    let start = match table.find("_start") {
+        Some(symbol) => symbol,
+        None => {
+            kprintln!("Kernel symbol table has no _start, ignoring it");
+            return;
+        }
+    };
+    let bias = (crate::_start as *const () as usize).wrapping_sub(start.value);
+
+    kprintln!("Kernel symbols: {} entries, slide {:#x}", symtab.len() / formats::elf::ELF64_SYM_SIZE, bias);
+    *SYMBOLS.lock() = Some(KernelSymbols { table, bias });
+}
+
+/// A runtime address with the function it falls in, formatted as `name+0x12` when known
+pub struct Symbolized {
+    pub address: usize,
+    pub name: Option<&'static str>,
+    pub offset: usize,
+}
+
+impl fmt::Display for Symbolized {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name {
+            // {:#} leaves off the hash rustc appends to mangled names
+            Some(name) => write!(f, "{:#x} {:#}+{:#x}", self.address, rustc_demangle::demangle(name), self.offset),
+            None => write!(f, "{:#x}", self.address),
+        }
+    }
+}
+
+/// Resolves a runtime address to the kernel function containing it.
+/// Uses try_lock, so it is safe to call from a panic that happened while the table was locked.
+pub fn resolve(address: usize) -> Symbolized {
+    let found = SYMBOLS.try_lock().and_then(|symbols| {
+        let symbols = symbols.as_ref()?;
+        symbols.table.lookup(address.wrapping_sub(symbols.bias))
+    });
+
+    match found {
+        Some((symbol, offset)) => Symbolized { address, name: Some(symbol.name), offset },
+        None => Symbolized { address, name: None, offset: 0 },
+    }
+}
\ No newline at end of file
@@ -0,0 +1,290 @@
+use alloc::string::ToString;
+use core::arch::x86_64::__cpuid;
+use core::ops::{Add, Sub};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::time::Duration;
+use boot_protocol::BootInfo;
+use spin::mutex::Mutex;
+
+use crate::kernel::serial_io::{inb, outb};
+use crate::kernel::{apic, cpu};
+use crate::kprintln;
+
+pub const TICK_HZ: u64 = 1000;
+const MAX_TIMERS: usize = 32;
+
+// PIT channel 2, whose gate and output are wired to port 0x61 rather than to an IRQ
+const PIT_FREQUENCY: u64 = 1_193_182;
+const PIT_CHANNEL2: u16 = 0x42;
+const PIT_COMMAND: u16 = 0x43;
+const PIT_CHANNEL2_ONE_SHOT: u8 = 0b1011_0000; // Channel 2, low then high byte, mode 0
+const PORT_61: u16 = 0x61;
+const PORT_61_GATE: u8 = 1 << 0;
+const PORT_61_SPEAKER: u8 = 1 << 1;
+const PORT_61_OUT2: u8 = 1 << 5;
+const CALIBRATION_MS: u64 = 10;
+
+#[derive(Debug)]
+pub enum TimerError {
+    NoTickSource, // The local APIC timer is not running, so nothing fires callbacks
+    TooManyTimers,
+}
+
+/// A point on the TSC timeline, comparable and subtractable like std's Instant
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Instant(u64);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimerId(u64);
+
+#[derive(Clone, Copy)]
+struct Timer {
+    id: u64,
+    deadline: u64, // In ticks
+    period: Option<u64>,
+    callback: fn(),
+}
+
+static TSC_FREQUENCY: AtomicU64 = AtomicU64::new(0); // Hz
+static TICKS: AtomicU64 = AtomicU64::new(0);
+static TICKING: AtomicBool = AtomicBool::new(false);
+static NEXT_TIMER_ID: AtomicU64 = AtomicU64::new(1);
+// Fixed slots, since the tick handler must not allocate. Only touched with interrupts off.
+static TIMERS: Mutex<[Option<Timer>; MAX_TIMERS]> = Mutex::new([None; MAX_TIMERS]);
+
+fn cycles_to_duration(cycles: u64) -> Duration {
+    let frequency = TSC_FREQUENCY.load(Ordering::Relaxed).max(1);
+    Duration::from_nanos((cycles as u128 * 1_000_000_000 / frequency as u128) as u64)
+}
+
+fn duration_to_cycles(duration: Duration) -> u64 {
+    let frequency = TSC_FREQUENCY.load(Ordering::Relaxed);
+    (duration.as_nanos() * frequency as u128 / 1_000_000_000) as u64
+}
+
+fn duration_to_ticks(duration: Duration) -> u64 {
+    (duration.as_nanos() * TICK_HZ as u128).div_ceil(1_000_000_000).max(1) as u64
+}
+
+impl Instant {
+    pub fn now() -> Self {
+        Instant(cpu::rdtsc())
+    }
+
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        cycles_to_duration(self.0.saturating_sub(earlier.0))
+    }
+
+    #[allow(dead_code)]
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, duration: Duration) -> Instant {
+        Instant(self.0.saturating_add(duration_to_cycles(duration)))
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, earlier: Instant) -> Duration {
+        self.duration_since(earlier)
+    }
+}
+
+/// The TSC crystal ratio from CPUID leaf 0x15, when the CPU reports the crystal frequency
+fn cpuid_tsc_frequency() -> Option<u64> {
+    if __cpuid(0).eax < 0x15 {
+        return None;
+    }
+
+    let leaf = __cpuid(0x15);
+    if leaf.eax == 0 || leaf.ebx == 0 || leaf.ecx == 0 {
+        return None;
+    }
+    Some(leaf.ecx as u64 * leaf.ebx as u64 / leaf.eax as u64)
+}
+
+/// Busy waits `ms` milliseconds on PIT channel 2. Gives up, returning false, after `limit` TSC cycles
+/// in case there is no PIT to count down.
+fn pit_wait(ms: u64, limit: u64) -> bool {
+    let count = (PIT_FREQUENCY * ms / 1000) as u16;
+    unsafe {
+        let port_61 = inb(PORT_61) & !(PORT_61_GATE | PORT_61_SPEAKER);
+        outb(PORT_61, port_61);
+        outb(PIT_COMMAND, PIT_CHANNEL2_ONE_SHOT);
+        outb(PIT_CHANNEL2, count as u8);
+        outb(PIT_CHANNEL2, (count >> 8) as u8);
+        outb(PORT_61, port_61 | PORT_61_GATE);
+    }
+
+    let start = cpu::rdtsc();
+    while inb(PORT_61) & PORT_61_OUT2 == 0 {
+        if cpu::rdtsc() - start > limit {
+            return false;
+        }
+        core::hint::spin_loop();
+    }
+    true
+}
+
+/// Picks the TSC frequency from CPUID, then a PIT measurement, then the bootloader's measurement
+fn detect_tsc_frequency(boot_info: &BootInfo) -> u64 {
+    if let Some(frequency) = cpuid_tsc_frequency() {
+        kprintln!("TSC frequency from CPUID: {} kHz", frequency / 1000);
+        return frequency;
+    }
+
+    // Without a bootloader figure, allow up to ten billion cycles before deciding the PIT is missing
+    let limit = match boot_info.tsc_frequency {
+        0 => 10_000_000_000,
+        frequency => frequency * CALIBRATION_MS * 10 / 1000,
+    };
+    let start = cpu::rdtsc();
+    if pit_wait(CALIBRATION_MS, limit) {
+        let frequency = (cpu::rdtsc() - start) * 1000 / CALIBRATION_MS;
+        kprintln!("TSC frequency from the PIT: {} kHz", frequency / 1000);
+        return frequency;
+    }
+
+    kprintln!("PIT did not count down, using the bootloader's TSC frequency: {} kHz", boot_info.tsc_frequency / 1000);
+    boot_info.tsc_frequency
+}
+
+fn tick() {
+    let now = TICKS.fetch_add(1, Ordering::Relaxed) + 1;
+
+    // Callbacks run after the lock is dropped, so they may schedule or cancel timers themselves
+    let mut due: [Option<fn()>; MAX_TIMERS] = [None; MAX_TIMERS];
+    {
+        let mut timers = TIMERS.lock();
+        for (slot, due) in timers.iter_mut().zip(due.iter_mut()) {
+            let timer = match slot {
+                Some(t) if t.deadline <= now => t,
+                _ => continue,
+            };
+
+            *due = Some(timer.callback);
+            match timer.period {
+                Some(period) => timer.deadline = now + period,
+                None => *slot = None,
+            }
+        }
+    }
+
+    for callback in due.into_iter().flatten() {
+        callback();
+    }
+}
+
+/// Detects the TSC frequency and starts the local APIC timer at TICK_HZ, calibrated against the TSC.
+/// Must run after apic::init and before interrupts are enabled.
+pub fn init(boot_info: &BootInfo) {
+    let tsc_frequency = detect_tsc_frequency(boot_info);
+    if tsc_frequency == 0 {
+        kprintln!("TSC frequency unknown, timers are unavailable");
+        return;
+    }
+    TSC_FREQUENCY.store(tsc_frequency, Ordering::Relaxed);
+
+    let calibration = Duration::from_millis(CALIBRATION_MS);
+    let lapic_ticks = match apic::measure_timer(|| sleep(calibration)) {
+        Ok(ticks) => ticks as u64,
+        Err(e) => {
+            kprintln!("No local APIC timer ({:?}), timer callbacks are unavailable", e);
+            return;
+        }
+    };
+
+    let lapic_frequency = lapic_ticks * 1000 / CALIBRATION_MS;
+    match apic::start_periodic_timer((lapic_frequency / TICK_HZ) as u32, tick) {
+        Ok(vector) => {
+            TICKING.store(true, Ordering::Relaxed);
+            kprintln!("Local APIC timer at {} kHz, ticking at {} Hz on vector {:#x}", lapic_frequency / 1000, TICK_HZ, vector);
+        },
+        Err(e) => {
+            kprintln!("Failed to start the local APIC timer: {:?}", e);
+        },
+    }
+}
+
+#[allow(dead_code)]
+pub fn tsc_frequency() -> u64 {
+    TSC_FREQUENCY.load(Ordering::Relaxed)
+}
+
+/// Time since the CPU came out of reset, firmware and bootloader included
+pub fn uptime() -> Duration {
+    cycles_to_duration(cpu::rdtsc())
+}
+
+/// Timer interrupts since init, 0 if the timer never started
+#[allow(dead_code)]
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Waits at least `duration`. Halts between timer ticks when interrupts are on, otherwise spins on the TSC.
+pub fn sleep(duration: Duration) {
+    let deadline = Instant::now() + duration;
+    let halt = TICKING.load(Ordering::Relaxed) && cpu::interrupts_enabled();
+
+    while Instant::now() < deadline {
+        if halt {
+            cpu::wait_for_interrupt();
+        } else {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+fn schedule(delay: Duration, period: Option<Duration>, callback: fn()) -> Result<TimerId, TimerError> {
+    if !TICKING.load(Ordering::Relaxed) {
+        return Err(TimerError::NoTickSource);
+    }
+
+    let id = NEXT_TIMER_ID.fetch_add(1, Ordering::Relaxed);
+    cpu::without_interrupts(|| {
+        let mut timers = TIMERS.lock();
+        let slot = timers.iter_mut().find(|slot| slot.is_none()).ok_or(TimerError::TooManyTimers)?;
+        *slot = Some(Timer {
+            id,
+            deadline: TICKS.load(Ordering::Relaxed) + duration_to_ticks(delay),
+            period: period.map(duration_to_ticks),
+            callback,
+        });
+        Ok(TimerId(id))
+    })
+}
+
+/// Calls `callback` once, from the timer interrupt, after `delay`
+#[allow(dead_code)]
+pub fn schedule_once(delay: Duration, callback: fn()) -> Result<TimerId, TimerError> {
+    schedule(delay, None, callback)
+}
+
+/// Calls `callback` from the timer interrupt every `period`, until cancelled
+#[allow(dead_code)]
+pub fn schedule_periodic(period: Duration, callback: fn()) -> Result<TimerId, TimerError> {
+    schedule(period, Some(period), callback)
+}
+
+/// Stops a pending timer. Returns false if it already fired (for one-shots) or was cancelled.
+#[allow(dead_code)]
+pub fn cancel(timer: TimerId) -> bool {
+    cpu::without_interrupts(|| {
+        let mut timers = TIMERS.lock();
+        match timers.iter_mut().find(|slot| matches!(slot, Some(t) if t.id == timer.0)) {
+            Some(slot) => {
+                *slot = None;
+                true
+            },
+            None => false,
+        }
+    })
+}
\ No newline at end of file
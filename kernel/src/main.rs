@@ -5,81 +5,145 @@ mod drawing;
 mod kernel;
 extern crate alloc;
 
-use alloc::boxed::Box;
+use boot_protocol::BootInfo;
 use drawing::*;
 use linked_list_allocator::LockedHeap;
 
 use crate::drawing::fonts::draw_string_raw;
-use crate::kernel::serial_io::serial_init;
+use crate::kernel::{acpi, apic, boot_timing, cmdline, cpu, crash_screen, firmware, gdt, idt, symbols, timer};
+use crate::kernel::frame_allocator::{self, FRAME_SIZE};
+use crate::kernel::initrd;
+use crate::kernel::paging;
+use crate::kernel::serial_io::{serial_init, serial_write_str};
 use crate::kernel::{string_api::Shell, Kernel};
 use crate::kernel::{ahci, pci, prelude::*};
-//use crate::alloc::string::ToString;
+use crate::alloc::string::ToString;
 
 #[global_allocator]
 static ALLOCATOR: LockedHeap = LockedHeap::empty();
 
-const HEAP_START: *mut u8 = 0x3000000 as *mut u8; // 48 MB into memory
 const HEAP_SIZE: usize = 0x100000; // 1 MB Heap
 
 const MAIN_FONT: &[u8] = include_bytes!("drawing/font.psf");
 
-#[repr(C)]
-pub struct FramebufferInfo<'a> {
-    pub buffer: &'a mut [u32],
-    pub size: usize,
-    pub width: usize,
-    pub height: usize,
-    pub pixels_per_scan_line: usize,
-}
-
-pub fn kernel_heap_init() {
+fn kernel_heap_init(heap_start: *mut u8) {
     unsafe {
-        ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
+        ALLOCATOR.lock().init(heap_start, HEAP_SIZE);
     }
 }
 
+/// Entered from the bootloader with a pointer to its BootInfo, which stays mapped and unchanged for good
 #[unsafe(no_mangle)]
-pub extern "sysv64" fn _start(fb_info: *mut FramebufferInfo) -> ! {
-    kernel_heap_init();
-    let fb_box = unsafe {
-        Box::from_raw(fb_info)
+extern "sysv64" fn _start(boot_info: *const BootInfo) -> ! {
+    let entry_tsc = cpu::rdtsc();
+    let boot_info: &'static BootInfo = unsafe { &*boot_info };
+    if !boot_info.is_valid() {
+        serial_write_str("Boot info has the wrong magic number, layout version or size, halting\n");
+        cpu::halt();
+    }
+    crash_screen::init(boot_info);
+
+    if !frame_allocator::init(boot_info) {
+        crash_screen::fatal("No free memory region can hold the frame allocator's bitmap");
+    }
+
+    let heap_start = match frame_allocator::allocate_contiguous(HEAP_SIZE / FRAME_SIZE, FRAME_SIZE) {
+        Some(start) => start,
+        None => crash_screen::fatal("Out of physical memory for the kernel heap"),
+    };
+    kernel_heap_init(heap_start as *mut u8);
+    let gdt_loaded = gdt::init();
+    idt::init(gdt_loaded);
+    boot_timing::init(boot_info, entry_tsc);
+    boot_timing::mark("memory");
+
+    let framebuffer = unsafe {
+        Framebuffer::from_info(&*boot_info.framebuffer)
     };
 
     let shell = Shell::new();
-    let mut kernel = Kernel::start(fb_box, shell);
+    let mut kernel = Kernel::start(framebuffer, shell);
     kernel.fill_screen(Color::Black);
 
     serial_init();
+    cmdline::init(boot_info);
+    kprintln!("Command line: \"{}\"", cmdline::get().as_str());
+    symbols::init(boot_info);
+    kprintln!("Entry point: {}", symbols::resolve(_start as *const () as usize));
+    kprintln!("Kernel stack: {:#x}-{:#x}, guard page at {:#x}",
+        boot_info.stack_virtual_base,
+        boot_info.stack_virtual_base + boot_info.stack_size,
+        boot_info.stack_virtual_base - paging::PAGE_SIZE as u64);
+    kprintln!("Kernel heap at {:#x}", heap_start);
+    if let Some(stats) = frame_allocator::stats() {
+        kprintln!("Physical frames: {} total, {} free, {} used, {} reserved",
+            stats.total_frames, stats.free_frames, stats.used_frames, stats.reserved_frames);
+    }
+    if let Some(allocator) = frame_allocator::FRAME_ALLOCATOR.lock().as_ref() {
+        for region in allocator.reserved_regions() {
+            kprintln!("Reserved {}: {:#x}-{:#x}", region.name, region.start, region.end);
+        }
+    }
 
-    pci::scan_pci_devices();
-    if let Some(hba) = ahci::scan_pci_for_ahci() {
-        if let Some(port_index) = ahci::find_ahci_device(&hba) {
-            let port = hba.ports[port_index].clone();
-            ahci::stop_command_engine(port.clone());
-            ahci::initialize_port(port.clone());
+    boot_timing::mark("console");
+
+    paging::init(boot_info);
+    gdt::unmap_ist_guards();
+    boot_timing::mark("paging");
+    initrd::init(boot_info);
+    firmware::init(boot_info);
+    acpi::init(boot_info);
+    let apic_enabled = apic::init();
+    timer::init(boot_info);
+    if apic_enabled {
+        cpu::enable_interrupts();
+    }
+    boot_timing::mark("firmware tables");
 
-            ahci::cmd_management::create_command_header(port.clone());
+    if !cmdline::flag("nopci") {
+        pci::scan_pci_devices();
+    }
+    boot_timing::mark("pci");
 
-            ahci::cmd_management::check_integrity(port.clone());
+    let hba = if cmdline::flag("nopci") || cmdline::flag("noahci") {
+        kprintln!("AHCI disabled on the command line");
+        None
+    } else {
+        ahci::scan_pci_for_ahci()
+    };
+    if let Some(hba) = hba && let Some(port_index) = ahci::find_ahci_device(&hba) {
+        let port = hba.ports[port_index].clone();
+        ahci::stop_command_engine(port.clone());
+        ahci::initialize_port(port.clone());
 
-            ahci::cmd_management::setup_command_table(port.clone());
+        ahci::cmd_management::create_command_header(port.clone());
 
-            ahci::cmd_management::check_integrity(port.clone());
+        ahci::cmd_management::check_integrity(port.clone());
 
-            ahci::cmd_management::create_prdt_entry(port.clone());
+        ahci::cmd_management::setup_command_table(port.clone());
 
-            ahci::cmd_management::check_integrity(port.clone());
+        ahci::cmd_management::check_integrity(port.clone());
 
-            ahci::cmd_management::issue_command(port.clone());
+        ahci::cmd_management::create_prdt_entry(port.clone());
 
-            ahci::cmd_management::check_integrity(port.clone());
+        ahci::cmd_management::check_integrity(port.clone());
 
-            //ahci::cmd_management::read_data_buffer(port.clone());
-        }
+        ahci::cmd_management::issue_command(port.clone(), hba.port_registers(port_index));
+
+        ahci::cmd_management::check_integrity(port.clone());
+
+        //ahci::cmd_management::read_data_buffer(port.clone());
     }
+    boot_timing::mark("ahci");
+    boot_timing::report();
+    kprintln!("Boot finished {} ms after reset", timer::uptime().as_millis());
 
     KERNEL_EVENT_MANAGER.lock().run(&mut kernel);
     KERNEL_EVENT_MANAGER.lock().clean_events();
+
+    if cmdline::flag("poweroff") {
+        firmware::shutdown();
+    }
     
     loop {
 
@@ -88,6 +152,6 @@ pub extern "sysv64" fn _start(fb_info: *mut FramebufferInfo) -> ! {
 
 #[cfg(not(test))]
 #[panic_handler]
-fn panic(_info: &core::panic::PanicInfo) -> ! {
-    loop {}
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    crash_screen::panic(info)
 }
\ No newline at end of file